@@ -0,0 +1,123 @@
+use db::{
+    establish_blob_store, establish_connection,
+    export::{self, ExportFormat, ExportOptions},
+    filter::BookFilter,
+    storage::Storage,
+};
+use dotenvy::dotenv;
+use std::{
+    env,
+    io::{self, BufWriter, Write},
+    process::ExitCode,
+};
+
+const USAGE: &str = "\
+usage: library <command>
+
+commands:
+    check [--repair]  compare stored files with the catalog
+    verify            rehash stored files and report corrupted ones
+    export <format> [--reviews] [--archived]
+                      write the catalog to stdout as csv, jsonl, marc or
+                      marcxml, with review stats or only archived books";
+
+fn main() -> ExitCode {
+    dotenv().ok();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args[..] {
+        ["check"] => check(false),
+        ["check", "--repair"] => check(true),
+        ["verify"] => verify(),
+        ["export", format, ref flags @ ..] => match export_options(format, flags) {
+            Some((format, options)) => export(format, &options),
+            None => {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            }
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Returns whether the store is consistent, or has been repaired.
+fn check(repair: bool) -> Result<bool, db::Error> {
+    let mut conn = establish_connection();
+    let storage = Storage::new(establish_blob_store());
+    let report = storage.check(&mut conn)?;
+    for key in &report.orphaned_files {
+        println!("orphaned {key}");
+    }
+    for key in &report.legacy_files {
+        println!("legacy   {key}");
+    }
+    for file in &report.missing_files {
+        println!(
+            "missing  {:?} of {} ({})",
+            file.kind, file.isbn, file.sha256
+        );
+    }
+    if report.is_consistent() {
+        return Ok(true);
+    }
+    if repair {
+        storage.repair(&mut conn, &report)?;
+        println!("repaired");
+    }
+    Ok(repair)
+}
+
+/// Returns whether every stored file matches its checksum.
+fn verify() -> Result<bool, db::Error> {
+    let mut conn = establish_connection();
+    let storage = Storage::new(establish_blob_store());
+    let mismatches = storage.verify(&mut conn)?;
+    for mismatch in &mismatches {
+        let files = mismatch
+            .files
+            .iter()
+            .map(|file| format!("{:?} of {}", file.kind, file.isbn))
+            .collect::<Vec<_>>()
+            .join(", ");
+        match &mismatch.actual {
+            None => println!("missing    {} ({files})", mismatch.sha256),
+            Some((sha256, size)) => println!(
+                "corrupted  {} ({files}): found {sha256}, {size} bytes",
+                mismatch.sha256
+            ),
+        }
+    }
+    Ok(mismatches.is_empty())
+}
+
+fn export_options(format: &str, flags: &[&str]) -> Option<(ExportFormat, ExportOptions)> {
+    let mut options = ExportOptions::default();
+    for flag in flags {
+        match *flag {
+            "--reviews" => options.reviews = true,
+            "--archived" => options.filter = BookFilter::new().archived(),
+            _ => return None,
+        }
+    }
+    Some((format.parse().ok()?, options))
+}
+
+fn export(format: ExportFormat, options: &ExportOptions) -> Result<bool, db::Error> {
+    let mut conn = establish_connection();
+    let mut out = BufWriter::new(io::stdout().lock());
+    let count = export::export(&mut conn, format, options, &mut out)?;
+    out.flush()?;
+    eprintln!("exported {count} books");
+    Ok(true)
+}
@@ -0,0 +1,112 @@
+//! Where the bytes of covers and book files live.
+//!
+//! Blobs are addressed by `/`-separated keys such as `objects/{sha256}`.
+//! The backend is picked with `BLOB_STORE` in `.env`, see
+//! [`crate::establish_blob_store`].
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
+};
+
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "s3")]
+pub use s3::S3Store;
+
+pub trait BlobStore: Send + Sync {
+    /// Stores everything read from `data` under `key`, replacing any previous blob.
+    fn put(&self, key: &str, data: &mut dyn Read) -> io::Result<()>;
+
+    /// Fails with [`io::ErrorKind::NotFound`] if there is no such blob.
+    fn get(&self, key: &str) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Like [`Self::get`], reading at most `len` bytes from offset `start`.
+    fn get_range(&self, key: &str, start: u64, len: u64) -> io::Result<Box<dyn Read + Send>> {
+        let mut blob = self.get(key)?;
+        io::copy(&mut (&mut blob).take(start), &mut io::sink())?;
+        Ok(Box::new(blob.take(len)))
+    }
+
+    /// Succeeds if there is no such blob.
+    fn delete(&self, key: &str) -> io::Result<()>;
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+
+    fn exists(&self, key: &str) -> io::Result<bool>;
+
+    /// Keys of all blobs directly under the directory `dir`, e.g. `covers`.
+    fn list(&self, dir: &str) -> io::Result<Vec<String>>;
+}
+
+/// Blobs as files under a root directory, the key being the relative path.
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    fn create_parent(&self, key: &str) -> io::Result<PathBuf> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(path)
+    }
+}
+
+impl BlobStore for LocalStore {
+    fn put(&self, key: &str, data: &mut dyn Read) -> io::Result<()> {
+        let mut file = File::create(self.create_parent(key)?)?;
+        io::copy(data, &mut file)?;
+        file.sync_all()
+    }
+
+    fn get(&self, key: &str) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(self.path(key))?))
+    }
+
+    fn get_range(&self, key: &str, start: u64, len: u64) -> io::Result<Box<dyn Read + Send>> {
+        let mut file = File::open(self.path(key))?;
+        file.seek(SeekFrom::Start(start))?;
+        Ok(Box::new(file.take(len)))
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        fs::rename(self.path(from), self.create_parent(to)?)
+    }
+
+    fn exists(&self, key: &str) -> io::Result<bool> {
+        self.path(key).try_exists()
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(self.path(dir)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            result => result?,
+        };
+        let mut keys = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                keys.push(format!("{dir}/{}", entry.file_name().to_string_lossy()));
+            }
+        }
+        Ok(keys)
+    }
+}
@@ -0,0 +1,418 @@
+//! People credited on books, recorded once in `authors` and linked to each
+//! book with a role.
+//!
+//! `books.author` stays the byline that is shown, searched and sorted by. It
+//! is kept in step with the contributors in the author role: setting the
+//! contributors rewrites the byline, and changing the byline replaces the
+//! authors with the names split from it.
+//!
+//! `authors` are authority records: besides the preferred name that bylines
+//! use, an author has alternate names in `author_names`, and a contributor
+//! given under any of them is linked to the same author. Names are found by
+//! their `search_key`, which folds spellings and Cyrillic and Latin
+//! transliterations of a name together (see the `name_key` SQL function).
+
+use crate::{
+    models::{
+        Author, AuthorName, Book, Contributor, ContributorRole, Isbn, NewAuthorName,
+        NewContributor, Script,
+    },
+    name_key,
+    schema::{author_names, authors, book_contributors, books},
+    search::MAX_RESULTS,
+    validation, Error,
+};
+use diesel::{
+    dsl::exists, pg::PgConnection, prelude::*, sql_query, sql_types::Integer, upsert::excluded,
+};
+
+/// Merges the contributions of one author into another, except where the
+/// other is already credited in the same role.
+const MOVE_CONTRIBUTIONS: &str = "
+    UPDATE book_contributors SET author_id = $1
+    WHERE author_id = $2 AND NOT EXISTS (
+        SELECT FROM book_contributors AS other
+        WHERE other.isbn = book_contributors.isbn
+            AND other.role = book_contributors.role
+            AND other.author_id = $1
+    )";
+
+/// Splits a byline into names. Names are separated by `;`, `&` or `and`, and
+/// by commas unless that would leave a part without a space, so that an
+/// inverted `Rowling, J. K.` stays one name.
+pub fn split_names(byline: &str) -> Vec<String> {
+    let byline = byline.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut names = Vec::new();
+    for part in byline
+        .split(';')
+        .flat_map(|part| part.split(" & "))
+        .flat_map(|part| part.split(" and "))
+    {
+        let pieces: Vec<&str> = part.split(',').map(str::trim).collect();
+        if pieces.iter().all(|piece| piece.contains(' ')) {
+            names.extend(pieces.into_iter().map(String::from));
+        } else {
+            names.push(part.trim().into());
+        }
+    }
+    names.retain(|name| !name.is_empty());
+    names
+}
+
+/// The names of the contributors in the author role, as a byline. Names are
+/// separated by `;`, so that [`split_names`] reads inverted names with a
+/// comma back as one.
+pub fn byline(contributors: &[NewContributor]) -> String {
+    contributors
+        .iter()
+        .filter(|contributor| contributor.role == ContributorRole::Author)
+        .map(|contributor| contributor.name.trim())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+pub(crate) fn get(conn: &mut PgConnection, isbn: Isbn) -> Result<Vec<Contributor>, Error> {
+    Ok(book_contributors::table
+        .inner_join(authors::table)
+        .filter(book_contributors::isbn.eq(isbn))
+        .order(book_contributors::position)
+        .select((authors::id, authors::name, book_contributors::role))
+        .load(conn)?)
+}
+
+pub(crate) fn set(
+    conn: &mut PgConnection,
+    isbn: Isbn,
+    contributors: &[NewContributor],
+) -> Result<Vec<Contributor>, Error> {
+    validation::ensure_valid(validation::check_contributors(contributors))?;
+    conn.transaction(|conn| {
+        books::table
+            .find(isbn)
+            .select(books::isbn)
+            .for_update()
+            .first::<Isbn>(conn)?;
+        write(conn, isbn, contributors)?;
+        get(conn, isbn)
+    })
+}
+
+/// Replaces the authors of a book with the names in `byline`, keeping the
+/// other contributors after them.
+pub(crate) fn set_authors(conn: &mut PgConnection, isbn: Isbn, byline: &str) -> Result<(), Error> {
+    let mut contributors: Vec<NewContributor> = split_names(byline)
+        .into_iter()
+        .map(|name| NewContributor {
+            name: name.into(),
+            role: ContributorRole::Author,
+        })
+        .collect();
+    contributors.extend(
+        get(conn, isbn)?
+            .into_iter()
+            .filter(|contributor| contributor.role != ContributorRole::Author)
+            .map(|contributor| NewContributor {
+                name: contributor.name.into(),
+                role: contributor.role,
+            }),
+    );
+    write(conn, isbn, &contributors)
+}
+
+/// Replaces the rows of the book in `book_contributors`, adding names that
+/// are not known as a preferred or alternate name to `authors`, and rewrites
+/// the byline from the preferred names. A name listed twice in the same role
+/// is kept once.
+fn write(
+    conn: &mut PgConnection,
+    isbn: Isbn,
+    contributors: &[NewContributor],
+) -> Result<(), Error> {
+    diesel::delete(book_contributors::table.filter(book_contributors::isbn.eq(isbn)))
+        .execute(conn)?;
+    for (position, contributor) in (0..).zip(contributors) {
+        let name = contributor.name.trim();
+        let alternate = author_names::table
+            .find(name)
+            .select(author_names::author_id)
+            .first::<i32>(conn)
+            .optional()?;
+        let author_id = match alternate {
+            Some(author_id) => author_id,
+            None => diesel::insert_into(authors::table)
+                .values(authors::name.eq(name))
+                .on_conflict(authors::name)
+                // a no-op update, so that the existing id is returned
+                .do_update()
+                .set(authors::name.eq(excluded(authors::name)))
+                .returning(authors::id)
+                .get_result::<i32>(conn)?,
+        };
+        diesel::insert_into(book_contributors::table)
+            .values((
+                book_contributors::isbn.eq(isbn),
+                book_contributors::author_id.eq(author_id),
+                book_contributors::role.eq(contributor.role),
+                book_contributors::position.eq(position),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    refresh_byline(conn, isbn)
+}
+
+/// Sets the byline of a book to the preferred names of its authors, unless
+/// it has none.
+fn refresh_byline(conn: &mut PgConnection, isbn: Isbn) -> Result<(), Error> {
+    let names = book_contributors::table
+        .inner_join(authors::table)
+        .filter(book_contributors::isbn.eq(isbn))
+        .filter(book_contributors::role.eq(ContributorRole::Author))
+        .order(book_contributors::position)
+        .select(authors::name)
+        .load::<String>(conn)?;
+    if !names.is_empty() {
+        diesel::update(books::table.find(isbn))
+            .set(books::author.eq(names.join("; ")))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Refreshes the bylines of the books the author wrote.
+fn refresh_bylines(conn: &mut PgConnection, author_id: i32) -> Result<(), Error> {
+    let isbns = book_contributors::table
+        .select(book_contributors::isbn)
+        .filter(book_contributors::author_id.eq(author_id))
+        .filter(book_contributors::role.eq(ContributorRole::Author))
+        .load::<Isbn>(conn)?;
+    for isbn in isbns {
+        refresh_byline(conn, isbn)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn get_author(conn: &mut PgConnection, id: i32) -> Result<Author, Error> {
+    Ok(authors::table
+        .find(id)
+        .select((authors::id, authors::name))
+        .first(conn)?)
+}
+
+/// Authors with a preferred or alternate name containing `query`, compared
+/// by search key.
+pub(crate) fn find_authors(
+    conn: &mut PgConnection,
+    query: &str,
+    limit: i64,
+) -> Result<Vec<Author>, Error> {
+    let key = diesel::select(name_key(query)).get_result::<String>(conn)?;
+    let pattern = format!("%{key}%");
+    Ok(authors::table
+        .filter(
+            authors::search_key.like(&pattern).or(authors::id.eq_any(
+                author_names::table
+                    .select(author_names::author_id)
+                    .filter(author_names::search_key.like(&pattern)),
+            )),
+        )
+        .select((authors::id, authors::name))
+        .order(authors::name)
+        .limit(limit.clamp(1, MAX_RESULTS))
+        .load(conn)?)
+}
+
+pub(crate) fn get_names(conn: &mut PgConnection, id: i32) -> Result<Vec<AuthorName>, Error> {
+    Ok(author_names::table
+        .filter(author_names::author_id.eq(id))
+        .select((author_names::name, author_names::script))
+        .order(author_names::name)
+        .load(conn)?)
+}
+
+pub(crate) fn add_name(
+    conn: &mut PgConnection,
+    id: i32,
+    name: &NewAuthorName,
+) -> Result<AuthorName, Error> {
+    let name = AuthorName {
+        name: name.name.trim().into(),
+        script: name.script.unwrap_or_else(|| Script::detect(&name.name)),
+    };
+    validation::ensure_valid(validation::check_author_name(&name.name))?;
+    conn.transaction(|conn| {
+        get_author(conn, id)?;
+        ensure_not_preferred(conn, &name.name)?;
+        diesel::insert_into(author_names::table)
+            .values((
+                author_names::name.eq(&name.name),
+                author_names::author_id.eq(id),
+                author_names::script.eq(name.script),
+            ))
+            .execute(conn)?;
+        Ok(name)
+    })
+}
+
+pub(crate) fn remove_name(conn: &mut PgConnection, id: i32, name: &str) -> Result<usize, Error> {
+    Ok(diesel::delete(
+        author_names::table
+            .filter(author_names::author_id.eq(id))
+            .filter(author_names::name.eq(name)),
+    )
+    .execute(conn)?)
+}
+
+/// Makes `name` the preferred name of the author and keeps the old one as
+/// an alternate name.
+pub(crate) fn set_preferred_name(
+    conn: &mut PgConnection,
+    id: i32,
+    name: &str,
+) -> Result<Author, Error> {
+    let name = name.trim();
+    validation::ensure_valid(validation::check_author_name(name))?;
+    conn.transaction(|conn| {
+        let author = authors::table
+            .find(id)
+            .select((authors::id, authors::name))
+            .for_update()
+            .first::<Author>(conn)?;
+        if author.name == name {
+            return Ok(author);
+        }
+        ensure_not_preferred(conn, name)?;
+        let owner = author_names::table
+            .find(name)
+            .select(author_names::author_id)
+            .first::<i32>(conn)
+            .optional()?;
+        if owner.is_some_and(|owner| owner != id) {
+            return Err(Error::InvalidInput(format!(
+                "`{name}` is a name of another author"
+            )));
+        }
+        diesel::delete(author_names::table.find(name)).execute(conn)?;
+        diesel::update(authors::table.find(id))
+            .set(authors::name.eq(name))
+            .execute(conn)?;
+        diesel::insert_into(author_names::table)
+            .values((
+                author_names::name.eq(&author.name),
+                author_names::author_id.eq(id),
+                author_names::script.eq(Script::detect(&author.name)),
+            ))
+            .execute(conn)?;
+        refresh_bylines(conn, id)?;
+        get_author(conn, id)
+    })
+}
+
+/// Merges `from` into `into`: its contributions and names, its preferred
+/// name becoming an alternate one, are moved and it is deleted.
+pub(crate) fn merge(conn: &mut PgConnection, into: i32, from: i32) -> Result<Author, Error> {
+    if into == from {
+        return Err(Error::InvalidInput(
+            "an author cannot be merged into itself".into(),
+        ));
+    }
+    conn.transaction(|conn| {
+        get_author(conn, into)?;
+        let merged = get_author(conn, from)?;
+        sql_query(MOVE_CONTRIBUTIONS)
+            .bind::<Integer, _>(into)
+            .bind::<Integer, _>(from)
+            .execute(conn)?;
+        diesel::delete(book_contributors::table.filter(book_contributors::author_id.eq(from)))
+            .execute(conn)?;
+        diesel::update(author_names::table.filter(author_names::author_id.eq(from)))
+            .set(author_names::author_id.eq(into))
+            .execute(conn)?;
+        diesel::delete(authors::table.find(from)).execute(conn)?;
+        diesel::insert_into(author_names::table)
+            .values((
+                author_names::name.eq(&merged.name),
+                author_names::author_id.eq(into),
+                author_names::script.eq(Script::detect(&merged.name)),
+            ))
+            .execute(conn)?;
+        refresh_bylines(conn, into)?;
+        get_author(conn, into)
+    })
+}
+
+/// Fails with [`Error::ForeignKeyViolation`] if the author is still
+/// credited on a book.
+pub(crate) fn delete_author(conn: &mut PgConnection, id: i32) -> Result<usize, Error> {
+    Ok(diesel::delete(authors::table.find(id)).execute(conn)?)
+}
+
+fn ensure_not_preferred(conn: &mut PgConnection, name: &str) -> Result<(), Error> {
+    if diesel::select(exists(authors::table.filter(authors::name.eq(name))))
+        .get_result::<bool>(conn)?
+    {
+        return Err(Error::InvalidInput(format!(
+            "`{name}` is the preferred name of an author, merge the authors instead"
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn books_by_contributor(
+    conn: &mut PgConnection,
+    author_id: i32,
+    role: Option<ContributorRole>,
+) -> Result<Vec<Book>, Error> {
+    let mut isbns = book_contributors::table
+        .select(book_contributors::isbn)
+        .filter(book_contributors::author_id.eq(author_id))
+        .into_boxed();
+    if let Some(role) = role {
+        isbns = isbns.filter(book_contributors::role.eq(role));
+    }
+    Ok(books::table
+        .filter(books::isbn.eq_any(isbns))
+        .filter(books::archived_at.is_null())
+        .order((books::issue_year, books::title))
+        .load(conn)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authors<'a>(names: &[&'a str]) -> Vec<NewContributor<'a>> {
+        names
+            .iter()
+            .map(|name| NewContributor {
+                name: (*name).into(),
+                role: ContributorRole::Author,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_names_of_byline() {
+        for names in [
+            &["Terry Pratchett"][..],
+            &["Neil Gaiman", "Terry Pratchett"],
+            &["Rowling, J. K.", "Tolkien, J. R. R."],
+            &[
+                "Strugatsky, Arkady",
+                "Boris Strugatsky",
+                "Аркадий Стругацкий",
+            ],
+        ] {
+            assert_eq!(split_names(&byline(&authors(names))), names);
+        }
+    }
+
+    #[test]
+    fn split_names_collapses_whitespace() {
+        assert_eq!(
+            split_names("  Neil \t Gaiman ;Terry  Pratchett and  Jane Doe "),
+            ["Neil Gaiman", "Terry Pratchett", "Jane Doe"]
+        );
+        assert_eq!(split_names("Rowling,  J. K."), ["Rowling, J. K."]);
+    }
+}
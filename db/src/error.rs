@@ -0,0 +1,132 @@
+use crate::validation::FieldError;
+use diesel::{
+    r2d2::PoolError,
+    result::{DatabaseErrorKind, Error as DieselError},
+    ConnectionError,
+};
+use serde::{Deserialize, Serialize};
+use speedy::{Readable, Writable};
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    InvalidInput(String),
+    /// Fields of a book or review that failed [`crate::validation`].
+    Validation(Vec<FieldError>),
+    HasReviews(i64),
+    UniqueViolation(String),
+    ForeignKeyViolation(String),
+    Connection(String),
+    Query(DieselError),
+    /// Reading or writing a cover or book file failed.
+    Io(io::Error),
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::NotFound => ErrorKind::NotFound,
+            Self::InvalidInput(_) => ErrorKind::BadRequest,
+            Self::Validation(_) => ErrorKind::Validation,
+            Self::HasReviews(_) => ErrorKind::HasReviews,
+            Self::UniqueViolation(_) => ErrorKind::UniqueViolation,
+            Self::ForeignKeyViolation(_) => ErrorKind::ForeignKeyViolation,
+            Self::Connection(_) => ErrorKind::Connection,
+            Self::Query(_) | Self::Io(_) => ErrorKind::Internal,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "record not found"),
+            Self::InvalidInput(msg) => write!(f, "invalid input: {msg}"),
+            Self::Validation(errors) => {
+                write!(f, "invalid input: ")?;
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{error}")?;
+                }
+                Ok(())
+            }
+            Self::HasReviews(count) => write!(f, "book has {count} reviews"),
+            Self::UniqueViolation(msg) => write!(f, "already exists: {msg}"),
+            Self::ForeignKeyViolation(msg) => write!(f, "references a missing record: {msg}"),
+            Self::Connection(msg) => write!(f, "database connection failed: {msg}"),
+            Self::Query(e) => e.fmt(f),
+            Self::Io(e) => write!(f, "file storage failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Query(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DieselError> for Error {
+    fn from(e: DieselError) -> Self {
+        match e {
+            DieselError::NotFound => Self::NotFound,
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+                Self::UniqueViolation(info.message().into())
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info) => {
+                Self::ForeignKeyViolation(info.message().into())
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ClosedConnection, info) => {
+                Self::Connection(info.message().into())
+            }
+            e => Self::Query(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ConnectionError> for Error {
+    fn from(e: ConnectionError) -> Self {
+        Self::Connection(e.to_string())
+    }
+}
+
+impl From<PoolError> for Error {
+    fn from(e: PoolError) -> Self {
+        Self::Connection(e.to_string())
+    }
+}
+
+/// Machine-readable classification of an error, sent to API clients.
+#[derive(Clone, Copy, Debug, Readable, Writable, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ErrorKind {
+    NotFound,
+    HasReviews,
+    UniqueViolation,
+    ForeignKeyViolation,
+    Connection,
+    BadRequest,
+    Internal,
+    Validation,
+}
+
+#[derive(Debug, Readable, Writable, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// What is wrong with each field, for [`ErrorKind::Validation`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
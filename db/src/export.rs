@@ -0,0 +1,306 @@
+//! Export of the catalog, written out page by page so that it never has to
+//! be held in memory as a whole.
+
+use crate::{
+    filter::BookFilter,
+    marc::{self, Record},
+    models::{Book, Isbn, Rating},
+    schema::{books, languages, reviews},
+    Error,
+};
+use diesel::{pg::PgConnection, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    str::FromStr,
+};
+
+/// Books loaded per query.
+const PAGE_SIZE: i64 = 500;
+/// Descriptions are split into several `520` fields to stay below the
+/// length limit of a MARC field.
+const MARC_SUMMARY_LEN: usize = 8_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    /// MARC 21 in ISO 2709.
+    Marc,
+    Marcxml,
+}
+
+impl ExportFormat {
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Jsonl => "application/x-ndjson",
+            Self::Marc => "application/marc",
+            Self::Marcxml => "application/marcxml+xml",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Jsonl => "jsonl",
+            Self::Marc => "mrc",
+            Self::Marcxml => "xml",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" => Ok(Self::Jsonl),
+            "marc" => Ok(Self::Marc),
+            "marcxml" => Ok(Self::Marcxml),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ExportOptions {
+    pub filter: BookFilter,
+    /// Add the number of reviews and the average rating of each book.
+    pub reviews: bool,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+struct ReviewStats {
+    review_count: usize,
+    /// `None` if there are no reviews.
+    average_rating: Option<f64>,
+}
+
+/// A book as written to CSV and JSON Lines, with the columns that
+/// [`crate::import`] reads back.
+#[derive(Serialize)]
+struct Row<'a> {
+    isbn: Isbn,
+    title: &'a str,
+    author: &'a str,
+    description: &'a str,
+    language: &'a str,
+    issue_year: i32,
+    #[serde(flatten)]
+    reviews: Option<ReviewStats>,
+}
+
+/// Writes the books selected by the filter to `out`, ordered by ISBN, and
+/// returns how many there were.
+pub fn export(
+    conn: &mut PgConnection,
+    format: ExportFormat,
+    options: &ExportOptions,
+    out: &mut dyn Write,
+) -> Result<usize, Error> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            let mut header = vec![
+                "isbn",
+                "title",
+                "author",
+                "description",
+                "language",
+                "issue_year",
+            ];
+            if options.reviews {
+                header.extend(["review_count", "average_rating"]);
+            }
+            writer.write_record(header).map_err(io::Error::from)?;
+            let count = for_each_book(conn, options, |book, stats| {
+                let mut record = vec![
+                    book.isbn.to_string(),
+                    book.title.clone(),
+                    book.author.clone(),
+                    book.description.clone(),
+                    book.language.clone(),
+                    book.issue_year.to_string(),
+                ];
+                if let Some(stats) = stats {
+                    record.push(stats.review_count.to_string());
+                    record.push(
+                        stats
+                            .average_rating
+                            .map_or_else(String::new, |rating| format!("{rating:.2}")),
+                    );
+                }
+                writer.write_record(record).map_err(io::Error::from)?;
+                Ok(())
+            })?;
+            writer.flush()?;
+            Ok(count)
+        }
+        ExportFormat::Jsonl => for_each_book(conn, options, |book, stats| {
+            let row = Row {
+                isbn: book.isbn,
+                title: &book.title,
+                author: &book.author,
+                description: &book.description,
+                language: &book.language,
+                issue_year: book.issue_year,
+                reviews: stats,
+            };
+            serde_json::to_writer(&mut *out, &row).map_err(io::Error::from)?;
+            out.write_all(b"\n")?;
+            Ok(())
+        }),
+        ExportFormat::Marc => {
+            let codes = marc_language_codes(conn)?;
+            for_each_book(conn, options, |book, stats| {
+                out.write_all(&marc_record(book, &codes, stats).to_iso2709()?)?;
+                Ok(())
+            })
+        }
+        ExportFormat::Marcxml => {
+            let codes = marc_language_codes(conn)?;
+            writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+            writeln!(out, r#"<collection xmlns="{}">"#, marc::MARCXML_NAMESPACE)?;
+            let count = for_each_book(conn, options, |book, stats| {
+                let mut xml = String::new();
+                marc_record(book, &codes, stats).write_xml(&mut xml);
+                out.write_all(xml.as_bytes())?;
+                Ok(())
+            })?;
+            writeln!(out, "</collection>")?;
+            Ok(count)
+        }
+    }
+}
+
+/// Calls `f` for every selected book, with its review stats if they were
+/// asked for.
+fn for_each_book(
+    conn: &mut PgConnection,
+    options: &ExportOptions,
+    mut f: impl FnMut(&Book, Option<ReviewStats>) -> Result<(), Error>,
+) -> Result<usize, Error> {
+    let mut count = 0;
+    let mut last = None;
+    loop {
+        let mut query = options.filter.apply(books::table.into_boxed());
+        if let Some(last) = last {
+            query = query.filter(books::isbn.gt(last));
+        }
+        let page = query
+            .order(books::isbn)
+            .limit(PAGE_SIZE)
+            .load::<Book>(conn)?;
+        let Some(book) = page.last() else {
+            return Ok(count);
+        };
+        last = Some(book.isbn);
+        let stats = if options.reviews {
+            review_stats(conn, &page)?
+        } else {
+            HashMap::new()
+        };
+        for book in &page {
+            let stats = options.reviews.then(|| {
+                stats.get(&book.isbn).copied().unwrap_or(ReviewStats {
+                    review_count: 0,
+                    average_rating: None,
+                })
+            });
+            f(book, stats)?;
+            count += 1;
+        }
+    }
+}
+
+fn review_stats(
+    conn: &mut PgConnection,
+    books: &[Book],
+) -> Result<HashMap<Isbn, ReviewStats>, Error> {
+    let isbns: Vec<Isbn> = books.iter().map(|book| book.isbn).collect();
+    let ratings = reviews::table
+        .select((reviews::isbn, reviews::rating))
+        .filter(reviews::isbn.eq_any(&isbns))
+        .load::<(Isbn, Rating)>(conn)?;
+    let mut totals: HashMap<Isbn, (usize, u32)> = HashMap::new();
+    for (isbn, rating) in ratings {
+        let (count, sum) = totals.entry(isbn).or_default();
+        *count += 1;
+        *sum += u32::from(rating.stars());
+    }
+    Ok(totals
+        .into_iter()
+        .map(|(isbn, (count, sum))| {
+            let stats = ReviewStats {
+                review_count: count,
+                average_rating: Some(f64::from(sum) / count as f64),
+            };
+            (isbn, stats)
+        })
+        .collect())
+}
+
+/// Bibliographic codes of the languages by their ISO 639-1 codes.
+fn marc_language_codes(conn: &mut PgConnection) -> Result<HashMap<String, String>, Error> {
+    Ok(languages::table
+        .select((languages::code, languages::bibliographic_code))
+        .load(conn)?
+        .into_iter()
+        .collect())
+}
+
+fn marc_record(book: &Book, codes: &HashMap<String, String>, stats: Option<ReviewStats>) -> Record {
+    let year = if (0..=9999).contains(&book.issue_year) {
+        format!("{:04}", book.issue_year)
+    } else {
+        "uuuu".into()
+    };
+    // every book has a language from the table, `und` is just in case
+    let language = codes.get(&book.language).map_or("und", String::as_str);
+    // date entered, single date of publication, unknown place, the rest of
+    // the book-specific positions not coded, language, cataloged here
+    let fixed = format!("||||||s{year}    xx {:|<17}{language} d", "");
+    let isbn = book.isbn.to_string();
+    let mut record = Record::new(Record::BOOK_LEADER)
+        .control("001", &isbn)
+        .control("008", fixed)
+        .data("020", [' ', ' '], &[('a', &isbn)])
+        .data("041", ['0', ' '], &[('a', language)])
+        .data("100", ['1', ' '], &[('a', &book.author)])
+        .data("245", ['1', '0'], &[('a', &book.title)])
+        .data("264", [' ', '1'], &[('c', &book.issue_year.to_string())]);
+    for summary in split_at_chars(&book.description, MARC_SUMMARY_LEN) {
+        record = record.data("520", [' ', ' '], &[('a', summary)]);
+    }
+    if let Some(stats) = stats {
+        let note = match stats.average_rating {
+            Some(rating) => format!(
+                "{} reviews, average rating {rating:.2} of 5",
+                stats.review_count
+            ),
+            None => "no reviews".into(),
+        };
+        record = record.data("590", [' ', ' '], &[('a', &note)]);
+    }
+    record
+}
+
+/// Splits `s` into pieces of at most `len` bytes, on character boundaries.
+fn split_at_chars(mut s: &str, len: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    while s.len() > len {
+        let mut end = len;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (piece, rest) = s.split_at(end);
+        pieces.push(piece);
+        s = rest;
+    }
+    pieces.push(s);
+    pieces
+}
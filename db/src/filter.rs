@@ -0,0 +1,128 @@
+use crate::{
+    name_key,
+    schema::{author_names, authors, book_contributors, books},
+};
+use diesel::{pg::Pg, prelude::*, sql_types::Text};
+use serde::{Deserialize, Serialize};
+
+/// Structured constraints on the `books` table, built up one at a time:
+///
+/// ```
+/// # use db::filter::BookFilter;
+/// let filter = BookFilter::new()
+///     .language("uk")
+///     .issued_between(1990, 2000);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookFilter {
+    /// [`crate::models::Language::code`].
+    pub language: Option<String>,
+    pub min_year: Option<i32>,
+    pub max_year: Option<i32>,
+    /// Case-insensitive substring of the byline, or part of a name of one of
+    /// the contributors in any spelling or transliteration.
+    pub author: Option<String>,
+    /// Select archived books instead of the ones in circulation.
+    #[serde(default)]
+    pub archived: bool,
+}
+
+impl BookFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn language(mut self, code: impl Into<String>) -> Self {
+        self.language = Some(code.into());
+        self
+    }
+
+    pub fn issued_after(mut self, year: i32) -> Self {
+        self.min_year = Some(year);
+        self
+    }
+
+    pub fn issued_before(mut self, year: i32) -> Self {
+        self.max_year = Some(year);
+        self
+    }
+
+    /// Both bounds are inclusive.
+    pub fn issued_between(self, from: i32, to: i32) -> Self {
+        self.issued_after(from).issued_before(to)
+    }
+
+    pub fn author(mut self, author: impl Into<String>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    pub fn archived(mut self) -> Self {
+        self.archived = true;
+        self
+    }
+
+    /// Whether the filter selects every book in circulation.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub(crate) fn apply(
+        &self,
+        mut query: books::BoxedQuery<'static, Pg>,
+    ) -> books::BoxedQuery<'static, Pg> {
+        query = if self.archived {
+            query.filter(books::archived_at.is_not_null())
+        } else {
+            query.filter(books::archived_at.is_null())
+        };
+        if let Some(language) = &self.language {
+            query = query.filter(books::language.eq(language.clone()));
+        }
+        if let Some(year) = self.min_year {
+            query = query.filter(books::issue_year.ge(year));
+        }
+        if let Some(year) = self.max_year {
+            query = query.filter(books::issue_year.le(year));
+        }
+        if let Some(author) = &self.author {
+            let pattern = || {
+                "%".into_sql::<Text>()
+                    .concat(name_key(author.clone()))
+                    .concat("%")
+            };
+            let contributions = book_contributors::table
+                .select(book_contributors::isbn)
+                .filter(
+                    book_contributors::author_id
+                        .eq_any(
+                            authors::table
+                                .select(authors::id)
+                                .filter(authors::search_key.like(pattern())),
+                        )
+                        .or(book_contributors::author_id.eq_any(
+                            author_names::table
+                                .select(author_names::author_id)
+                                .filter(author_names::search_key.like(pattern())),
+                        )),
+                );
+            query = query.filter(
+                books::author
+                    .ilike(format!("%{}%", escape_like(author)))
+                    .or(books::isbn.eq_any(contributions)),
+            );
+        }
+        query
+    }
+}
+
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
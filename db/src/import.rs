@@ -0,0 +1,641 @@
+//! Bulk import of books from CSV, JSON and MARC 21 files.
+//!
+//! Records have the fields of [`NewBook`]: `isbn`, `title`, `author`,
+//! `description` (which may be left out), `language` and `issue_year`, the
+//! language given as any of the codes or names in the `languages` table. CSV
+//! files name them in a header row; JSON files are either an array of
+//! objects or one object per line. MARC records, binary or MARCXML, are
+//! mapped from their fields as described in [`marc_record`].
+
+use crate::{
+    contributors, marc,
+    metadata::parse_year,
+    models::{Isbn, Language, NewBook},
+    schema::books,
+    validation, Error,
+};
+use diesel::{pg::PgConnection, prelude::*};
+use serde_json::Value;
+use std::{collections::HashSet, path::Path};
+
+const REQUIRED: [&str; 5] = ["isbn", "title", "author", "language", "issue_year"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+    /// MARC 21 in ISO 2709.
+    Marc,
+    MarcXml,
+}
+
+impl Format {
+    /// Guesses the format from the file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "json" | "jsonl" | "ndjson" => Some(Self::Json),
+            "mrc" | "marc" => Some(Self::Marc),
+            "xml" => Some(Self::MarcXml),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImportOptions {
+    /// Validate and look for duplicates without inserting anything.
+    pub dry_run: bool,
+    /// Rows inserted per transaction.
+    pub batch_size: usize,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            batch_size: 500,
+        }
+    }
+}
+
+/// A problem with one row, which is left out of the import.
+#[derive(Debug)]
+pub struct RowError {
+    /// The line in a CSV file or JSON Lines file, the position in a JSON array
+    /// or among the records of a MARC file.
+    pub row: usize,
+    /// `None` if the row as a whole could not be read or inserted.
+    pub field: Option<&'static str>,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub rows: usize,
+    /// Rows inserted, or that would be inserted in a dry run.
+    pub imported: usize,
+    /// Sorted by row.
+    pub errors: Vec<RowError>,
+}
+
+/// Reads every record of `data`, inserting the valid ones in batches.
+///
+/// Fails only if the file as a whole cannot be read; a row that is invalid,
+/// repeats an earlier ISBN or is already in the catalog is reported and
+/// skipped, and so is every row of a batch that failed to insert.
+pub fn import(
+    conn: &mut PgConnection,
+    format: Format,
+    data: &[u8],
+    options: &ImportOptions,
+) -> Result<ImportReport, Error> {
+    let records = match format {
+        Format::Csv => read_csv(data)?,
+        Format::Json => read_json(data)?,
+        Format::Marc => (1..)
+            .zip(marc::read_iso2709(data))
+            .map(|(row, record)| (row, record.map(|record| marc_record(&record))))
+            .collect(),
+        Format::MarcXml => (1..)
+            .zip(marc::read_xml(&String::from_utf8_lossy(data))?)
+            .map(|(row, record)| (row, Ok(marc_record(&record))))
+            .collect(),
+    };
+    let languages = crate::load_languages(conn)?;
+    let rows = records.len();
+    let (valid, errors) = check_records(records, &languages);
+    let mut report = ImportReport {
+        rows,
+        errors,
+        ..Default::default()
+    };
+
+    for batch in valid.chunks(options.batch_size.max(1)) {
+        let result = conn.transaction(|conn| {
+            let isbns: Vec<Isbn> = batch.iter().map(|(_, book)| book.isbn).collect();
+            let existing: HashSet<Isbn> = books::table
+                .select(books::isbn)
+                .filter(books::isbn.eq_any(&isbns))
+                .load::<Isbn>(conn)?
+                .into_iter()
+                .collect();
+            let new: Vec<&NewBook> = batch
+                .iter()
+                .map(|(_, book)| book)
+                .filter(|book| !existing.contains(&book.isbn))
+                .collect();
+            let imported = new.len();
+            if !options.dry_run {
+                diesel::insert_into(books::table)
+                    .values(new.clone())
+                    .execute(conn)?;
+                for book in new {
+                    contributors::set_authors(conn, book.isbn, &book.author)?;
+                }
+            }
+            Ok::<_, Error>((imported, existing))
+        });
+        match result {
+            Ok((imported, existing)) => {
+                report.imported += imported;
+                report.errors.extend(
+                    batch
+                        .iter()
+                        .filter(|(_, book)| existing.contains(&book.isbn))
+                        .map(|(row, book)| RowError {
+                            row: *row,
+                            field: Some("isbn"),
+                            message: format!("{} is already in the catalog", book.isbn),
+                        }),
+                );
+            }
+            Err(e) => report.errors.extend(batch.iter().map(|(row, _)| RowError {
+                row: *row,
+                field: None,
+                message: format!("batch failed to insert: {e}"),
+            })),
+        }
+    }
+    report.errors.sort_by_key(|error| error.row);
+    Ok(report)
+}
+
+/// Splits the records into the books to insert and the errors of the other
+/// rows, which are invalid or repeat an earlier ISBN.
+fn check_records(
+    records: Records,
+    languages: &[Language],
+) -> (Vec<(usize, NewBook<'static>)>, Vec<RowError>) {
+    let mut valid = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    for (row, record) in records {
+        let book = record
+            .map_err(|message| {
+                vec![RowError {
+                    row,
+                    field: None,
+                    message,
+                }]
+            })
+            .and_then(|record| record.validate(row, languages));
+        match book {
+            Ok(book) if !seen.insert(book.isbn) => errors.push(RowError {
+                row,
+                field: Some("isbn"),
+                message: format!("{} appears earlier in the file", book.isbn),
+            }),
+            Ok(book) => valid.push((row, book)),
+            Err(row_errors) => errors.extend(row_errors),
+        }
+    }
+    (valid, errors)
+}
+
+/// The fields of one record, as text.
+#[derive(Default)]
+struct Record {
+    isbn: Option<String>,
+    title: Option<String>,
+    author: Option<String>,
+    description: Option<String>,
+    language: Option<String>,
+    issue_year: Option<String>,
+}
+
+impl Record {
+    /// Unknown fields are ignored.
+    fn set(&mut self, name: &str, value: String) {
+        let field = match name {
+            "isbn" => &mut self.isbn,
+            "title" => &mut self.title,
+            "author" => &mut self.author,
+            "description" => &mut self.description,
+            "language" => &mut self.language,
+            "issue_year" => &mut self.issue_year,
+            _ => return,
+        };
+        let value = value.trim();
+        *field = (!value.is_empty()).then(|| value.to_owned());
+    }
+
+    /// Languages are looked up with [`Language::find`].
+    fn validate(
+        self,
+        row: usize,
+        languages: &[Language],
+    ) -> Result<NewBook<'static>, Vec<RowError>> {
+        let mut errors = Vec::new();
+        let mut error = |field, message| {
+            errors.push(RowError {
+                row,
+                field: Some(field),
+                message,
+            })
+        };
+        let fields = [
+            ("isbn", &self.isbn),
+            ("title", &self.title),
+            ("author", &self.author),
+            ("language", &self.language),
+            ("issue_year", &self.issue_year),
+        ];
+        for (name, value) in fields {
+            if value.is_none() {
+                error(name, "is missing".into());
+            }
+        }
+        let isbn = self.isbn.as_deref().and_then(|isbn| {
+            let parsed = isbn.parse().ok();
+            if parsed.is_none() {
+                error("isbn", format!("`{isbn}` is not a valid ISBN"));
+            }
+            parsed
+        });
+        let language = self.language.as_deref().and_then(|language| {
+            let found = Language::find(languages, language);
+            if found.is_none() {
+                error("language", format!("`{language}` is not a known language"));
+            }
+            found.map(|language| language.code.clone())
+        });
+        let issue_year = self.issue_year.as_deref().and_then(|year| {
+            let parsed = year.parse().ok();
+            if parsed.is_none() {
+                error("issue_year", format!("`{year}` is not a year"));
+            }
+            parsed
+        });
+        match (isbn, self.title, self.author, language, issue_year) {
+            (Some(isbn), Some(title), Some(author), Some(language), Some(issue_year))
+                if errors.is_empty() =>
+            {
+                let book = NewBook {
+                    isbn,
+                    title: title.into(),
+                    author: author.into(),
+                    description: self.description.unwrap_or_default().into(),
+                    language: language.into(),
+                    issue_year,
+                };
+                let errors: Vec<RowError> = validation::check_book(&book)
+                    .into_iter()
+                    .map(|error| RowError {
+                        row,
+                        field: Some(error.field.as_str()),
+                        message: error.message,
+                    })
+                    .collect();
+                if errors.is_empty() {
+                    Ok(book)
+                } else {
+                    Err(errors)
+                }
+            }
+            _ => Err(errors),
+        }
+    }
+}
+
+type Records = Vec<(usize, Result<Record, String>)>;
+
+fn read_csv(data: &[u8]) -> Result<Records, Error> {
+    let mut reader = csv::Reader::from_reader(data);
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| Error::InvalidInput(format!("unreadable CSV header: {e}")))?
+        .iter()
+        .map(|header| header.trim().to_ascii_lowercase())
+        .collect();
+    if let Some(missing) = REQUIRED
+        .iter()
+        .find(|column| !headers.iter().any(|header| header == *column))
+    {
+        return Err(Error::InvalidInput(format!("missing column `{missing}`")));
+    }
+    Ok(reader
+        .records()
+        .enumerate()
+        .map(|(i, record)| match record {
+            Ok(fields) => {
+                let row = fields.position().map_or(i + 2, |p| p.line() as usize);
+                let mut record = Record::default();
+                for (name, value) in headers.iter().zip(&fields) {
+                    record.set(name, value.into());
+                }
+                (row, Ok(record))
+            }
+            Err(e) => {
+                let row = e.position().map_or(i + 2, |p| p.line() as usize);
+                (row, Err(e.to_string()))
+            }
+        })
+        .collect())
+}
+
+fn read_json(data: &[u8]) -> Result<Records, Error> {
+    let text = String::from_utf8_lossy(data);
+    if text.trim_start().starts_with('[') {
+        let values: Vec<Value> = serde_json::from_str(&text)
+            .map_err(|e| Error::InvalidInput(format!("unreadable JSON: {e}")))?;
+        Ok((1..).zip(values.into_iter().map(json_record)).collect())
+    } else {
+        Ok((1..)
+            .zip(text.lines())
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(row, line)| {
+                let record = serde_json::from_str(line)
+                    .map_err(|e| e.to_string())
+                    .and_then(json_record);
+                (row, record)
+            })
+            .collect())
+    }
+}
+
+fn json_record(value: Value) -> Result<Record, String> {
+    let Value::Object(object) = value else {
+        return Err("not a JSON object".into());
+    };
+    let mut record = Record::default();
+    for (name, value) in object {
+        match value {
+            Value::Null => {}
+            Value::String(s) => record.set(&name, s),
+            Value::Number(n) => record.set(&name, n.to_string()),
+            _ => return Err(format!("`{name}` must be a string or a number")),
+        }
+    }
+    Ok(record)
+}
+
+/// Maps the fields of a MARC 21 bibliographic record:
+///
+/// - `isbn` from `020 $a`, the first one that is a valid ISBN;
+/// - `title` from `245 $a` and `$b`;
+/// - `author` from `100 $a`;
+/// - `description` from every `520 $a`, as paragraphs separated by a blank
+///   line;
+/// - `language` from `041 $a`, or else positions 35-37 of `008`;
+/// - `issue_year` from `264 $c` of the publication statement, or else
+///   `260 $c`, or else positions 07-10 of `008`.
+///
+/// Trailing ISBD punctuation is dropped. Values that cannot be used are kept
+/// as they are so that validation reports them.
+fn marc_record(marc: &marc::Record) -> Record {
+    let mut record = Record::default();
+    let first = |tag, code| marc.subfields(tag, code).next().map(str::to_owned);
+    let fixed: Vec<char> = marc
+        .control_field("008")
+        .unwrap_or_default()
+        .chars()
+        .collect();
+    // blanks and `|` mean that a position is not coded
+    let fixed = |range: std::ops::Range<usize>| {
+        let value: String = fixed.get(range)?.iter().collect();
+        let value = value.trim_matches([' ', '|']);
+        (!value.is_empty()).then(|| value.to_owned())
+    };
+
+    // `020 $a` may be followed by a qualifier, as in `0747542155 (pbk.)`
+    let isbns: Vec<&str> = marc
+        .subfields("020", 'a')
+        .filter_map(|isbn| isbn.split_whitespace().next())
+        .collect();
+    if let Some(isbn) = isbns
+        .iter()
+        .find(|isbn| isbn.parse::<Isbn>().is_ok())
+        .or(isbns.first())
+    {
+        record.set("isbn", (*isbn).into());
+    }
+
+    let title: Vec<&str> = ['a', 'b']
+        .into_iter()
+        .filter_map(|code| marc.subfields("245", code).next())
+        .map(trim_isbd)
+        .filter(|part| !part.is_empty())
+        .collect();
+    record.set("title", title.join(": "));
+    if let Some(author) = first("100", 'a') {
+        record.set("author", trim_isbd(&author).into());
+    }
+    record.set(
+        "description",
+        marc.subfields("520", 'a').collect::<Vec<_>>().join("\n\n"),
+    );
+
+    if let Some(language) = first("041", 'a').or_else(|| fixed(35..38)) {
+        record.set("language", language);
+    }
+
+    let published = marc.fields("264").find_map(|field| match field {
+        marc::Field::Data {
+            indicators: [_, '1'],
+            ..
+        } => field.subfields('c').next(),
+        _ => None,
+    });
+    let date = published
+        .map(str::to_owned)
+        .or_else(|| first("264", 'c'))
+        .or_else(|| first("260", 'c'))
+        .or_else(|| fixed(7..11).filter(|year| year.parse::<i32>().is_ok()));
+    if let Some(date) = date {
+        // dates like `c2001.` or `[2001]`
+        let year = parse_year(&date).map_or(date, |year| year.to_string());
+        record.set("issue_year", year);
+    }
+    record
+}
+
+/// Drops the punctuation that precedes the next subfield, as in
+/// `Harry Potter and the philosopher's stone /`.
+fn trim_isbd(value: &str) -> &str {
+    value.trim_end_matches([' ', '/', ':', ';', '=', ','])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn languages() -> Vec<Language> {
+        [
+            ("en", "eng", "eng", "English", "English"),
+            ("de", "ger", "deu", "German", "Deutsch"),
+        ]
+        .map(
+            |(code, bibliographic, terminology, english, native)| Language {
+                code: code.into(),
+                bibliographic_code: bibliographic.into(),
+                terminology_code: terminology.into(),
+                english_name: english.into(),
+                native_name: native.into(),
+            },
+        )
+        .into()
+    }
+
+    /// Row and field of each error.
+    fn error_fields(errors: &[RowError]) -> Vec<(usize, Option<&str>)> {
+        errors
+            .iter()
+            .map(|error| (error.row, error.field))
+            .collect()
+    }
+
+    #[test]
+    fn csv_missing_column() {
+        let data = b"isbn,title,author,issue_year\n9780747542155,Title,Author,1997\n";
+        match read_csv(data) {
+            Err(Error::InvalidInput(message)) => assert_eq!(message, "missing column `language`"),
+            _ => panic!("a file without a language column was read"),
+        }
+    }
+
+    #[test]
+    fn csv_quoting() {
+        let data = "ISBN, Title ,author,description,language,issue_year,shelf
+9780747542155,\"Harry Potter, \"\"the stone\"\"\",\"Rowling, J. K.\",\"two
+lines\",en,1997,A1
+9783150000014,Die Verwandlung,Franz Kafka,,German,1915,B2
+";
+        let records = read_csv(data.as_bytes()).unwrap();
+        let rows: Vec<usize> = records.iter().map(|(row, _)| *row).collect();
+        // the quoted line break does not start a row
+        assert_eq!(rows, [2, 4]);
+        let (valid, errors) = check_records(records, &languages());
+        assert!(errors.is_empty());
+        let book = &valid[0].1;
+        assert_eq!(book.title, "Harry Potter, \"the stone\"");
+        assert_eq!(book.author, "Rowling, J. K.");
+        assert_eq!(book.description, "two\nlines");
+        assert_eq!(valid[1].1.language, "de");
+        assert_eq!(valid[1].1.description, "");
+    }
+
+    #[test]
+    fn invalid_fields() {
+        let data = "isbn,title,author,language,issue_year
+9780747542156,Bad check digit,Author,en,1997
+9780747542155,Unknown language,Author,Klingon,1997
+9783150000014,,Author,de,year
+";
+        let (valid, errors) = check_records(read_csv(data.as_bytes()).unwrap(), &languages());
+        assert!(valid.is_empty());
+        assert_eq!(
+            error_fields(&errors),
+            [
+                (2, Some("isbn")),
+                (3, Some("language")),
+                (4, Some("title")),
+                (4, Some("issue_year")),
+            ]
+        );
+        assert_eq!(errors[1].message, "`Klingon` is not a known language");
+    }
+
+    #[test]
+    fn duplicate_within_file() {
+        let data = "isbn,title,author,language,issue_year
+9780747542155,First,Author,en,1997
+0-7475-4215-5,Same book as ISBN-10,Author,en,1997
+";
+        let (valid, errors) = check_records(read_csv(data.as_bytes()).unwrap(), &languages());
+        assert_eq!(valid.len(), 1);
+        assert_eq!(error_fields(&errors), [(3, Some("isbn"))]);
+        assert_eq!(
+            errors[0].message,
+            "9780747542155 appears earlier in the file"
+        );
+    }
+
+    #[test]
+    fn json_array_and_lines() {
+        let array = r#"[
+            {"isbn": "9780747542155", "title": "Title", "author": "Author",
+             "language": "en", "issue_year": 1997, "description": null},
+            {"isbn": 9783150000014, "title": "Titel", "author": "Autor",
+             "language": "de", "issue_year": "1915"}
+        ]"#;
+        let lines = r#"{"isbn": "9780747542155", "title": "Title", "author": "Author", "language": "en", "issue_year": 1997, "description": null}
+
+{"isbn": 9783150000014, "title": "Titel", "author": "Autor", "language": "de", "issue_year": "1915"}
+{"isbn": 9783150000014, "title": ["not", "text"]}
+[1, 2]
+not JSON
+"#;
+        let (from_array, errors) =
+            check_records(read_json(array.as_bytes()).unwrap(), &languages());
+        assert!(errors.is_empty());
+        let rows: Vec<usize> = from_array.iter().map(|(row, _)| *row).collect();
+        assert_eq!(rows, [1, 2]);
+
+        let (from_lines, errors) =
+            check_records(read_json(lines.as_bytes()).unwrap(), &languages());
+        // rows are line numbers, blank lines included
+        let rows: Vec<usize> = from_lines.iter().map(|(row, _)| *row).collect();
+        assert_eq!(rows, [1, 3]);
+        let fields = |books: &[(usize, NewBook)]| {
+            books
+                .iter()
+                .map(|(_, book)| {
+                    let NewBook {
+                        isbn,
+                        title,
+                        author,
+                        description,
+                        language,
+                        issue_year,
+                    } = book;
+                    (
+                        *isbn,
+                        title.to_string(),
+                        author.to_string(),
+                        description.to_string(),
+                        language.to_string(),
+                        *issue_year,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fields(&from_array), fields(&from_lines));
+        assert_eq!(error_fields(&errors), [(4, None), (5, None), (6, None)]);
+        assert_eq!(errors[0].message, "`title` must be a string or a number");
+        assert_eq!(errors[1].message, "not a JSON object");
+
+        assert!(matches!(
+            read_json(b"[{\"isbn\": 1},"),
+            Err(Error::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn marc_record_fields() {
+        let marc = marc::Record::new(marc::Record::BOOK_LEADER)
+            .control("008", "970626s1997    enk           000 1 eng d")
+            .data("020", [' ', ' '], &[('a', "invalid")])
+            .data("020", [' ', ' '], &[('a', "0747542155 (pbk.)")])
+            .data("100", ['1', ' '], &[('a', "Rowling, J. K.,")])
+            .data(
+                "245",
+                ['1', '0'],
+                &[('a', "Harry Potter /"), ('b', "the stone :")],
+            )
+            .data("520", [' ', ' '], &[('a', "First paragraph.")])
+            .data("520", [' ', ' '], &[('a', "Second paragraph.")])
+            .data("264", [' ', '4'], &[('c', "©1996")])
+            .data("264", [' ', '1'], &[('c', "[1997]")]);
+        let record = marc_record(&marc);
+        assert_eq!(record.isbn.as_deref(), Some("0747542155"));
+        assert_eq!(record.title.as_deref(), Some("Harry Potter: the stone"));
+        assert_eq!(record.author.as_deref(), Some("Rowling, J. K."));
+        assert_eq!(
+            record.description.as_deref(),
+            Some("First paragraph.\n\nSecond paragraph.")
+        );
+        assert_eq!(record.language.as_deref(), Some("eng"));
+        assert_eq!(record.issue_year.as_deref(), Some("1997"));
+    }
+}
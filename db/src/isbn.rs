@@ -0,0 +1,479 @@
+//! International Standard Book Numbers, stored as ISBN-13 in a `bigint`.
+
+use crate::Error;
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    serialize::{self, Output, ToSql},
+    sql_types::BigInt,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use speedy::{Context, Readable, Reader, Writable, Writer};
+use std::{fmt, str::FromStr};
+
+/// Length of the registration group identifier for ranges of the seven digits
+/// after the prefix, each given by its last value; 0 where nothing is
+/// assigned. From the International ISBN Agency's range message.
+const GROUPS: [(&str, &[(u32, usize)]); 2] = [
+    (
+        "978",
+        &[
+            (5_999_999, 1),
+            (6_499_999, 3),
+            (6_599_999, 2),
+            (6_999_999, 3),
+            (7_999_999, 1),
+            (9_499_999, 2),
+            (9_899_999, 3),
+            (9_989_999, 4),
+            (9_999_999, 5),
+        ],
+    ),
+    (
+        "979",
+        &[
+            (999_999, 0),
+            (1_299_999, 2),
+            (7_999_999, 0),
+            (8_999_999, 1),
+            (9_999_999, 0),
+        ],
+    ),
+];
+
+/// Length of the registrant (publisher) element for ranges of the seven
+/// digits after the group, in the registration groups of the catalog's
+/// languages.
+const REGISTRANTS: [(&str, &[(u32, usize)]); 7] = [
+    // English
+    (
+        "978-0",
+        &[
+            (1_999_999, 2),
+            (6_999_999, 3),
+            (8_499_999, 4),
+            (8_999_999, 5),
+            (9_499_999, 6),
+            (9_999_999, 7),
+        ],
+    ),
+    (
+        "978-1",
+        &[
+            (999_999, 2),
+            (3_999_999, 3),
+            (5_499_999, 4),
+            (8_697_999, 5),
+            (9_989_999, 6),
+            (9_999_999, 7),
+        ],
+    ),
+    // German
+    (
+        "978-3",
+        &[
+            (299_999, 2),
+            (339_999, 3),
+            (369_999, 4),
+            (399_999, 5),
+            (1_999_999, 2),
+            (6_999_999, 3),
+            (8_499_999, 4),
+            (8_999_999, 5),
+            (9_499_999, 6),
+            (9_539_999, 7),
+            (9_699_999, 5),
+            (9_849_999, 7),
+            (9_999_999, 5),
+        ],
+    ),
+    // Japanese
+    (
+        "978-4",
+        &[
+            (1_999_999, 2),
+            (6_999_999, 3),
+            (8_499_999, 4),
+            (8_999_999, 5),
+            (9_499_999, 6),
+            (9_999_999, 7),
+        ],
+    ),
+    // Russian
+    (
+        "978-5",
+        &[
+            (49_999, 5),
+            (99_999, 4),
+            (1_999_999, 2),
+            (3_619_999, 3),
+            (3_623_999, 4),
+            (3_629_999, 7),
+            (4_209_999, 3),
+            (4_299_999, 4),
+            (4_309_999, 3),
+            (4_399_999, 4),
+            (4_409_999, 3),
+            (4_499_999, 4),
+            (6_039_999, 3),
+            (6_049_999, 7),
+            (6_999_999, 3),
+            (8_499_999, 4),
+            (8_999_999, 5),
+            (9_099_999, 6),
+            (9_199_999, 5),
+            (9_299_999, 4),
+            (9_499_999, 5),
+            (9_500_999, 7),
+            (9_799_999, 4),
+            (9_899_999, 5),
+            (9_909_999, 7),
+            (9_999_999, 4),
+        ],
+    ),
+    // Chinese
+    (
+        "978-7",
+        &[
+            (999_999, 2),
+            (4_999_999, 3),
+            (7_999_999, 4),
+            (8_999_999, 5),
+            (9_999_999, 6),
+        ],
+    ),
+    // Ukrainian
+    (
+        "978-966",
+        &[
+            (1_299_999, 2),
+            (1_399_999, 3),
+            (1_499_999, 2),
+            (1_699_999, 4),
+            (1_999_999, 3),
+            (2_789_999, 4),
+            (2_899_999, 3),
+            (2_999_999, 4),
+            (6_999_999, 3),
+            (8_999_999, 4),
+            (9_099_999, 5),
+            (9_499_999, 3),
+            (9_799_999, 5),
+            (9_999_999, 3),
+        ],
+    ),
+];
+
+/// An ISBN-13; ISBN-10 is converted on parsing.
+///
+/// Stored and serialized as the 13-digit number, but also deserialized from
+/// strings in any of the forms that [`FromStr`] accepts. Only [`Isbn::new`]
+/// and [`FromStr`] check the number: books were stored with any number before
+/// ISBNs were validated, so deserializing and [`Isbn::parse_stored`] accept
+/// those too, and new books are checked by [`crate::validation`] instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, AsExpression, FromSqlRow)]
+#[diesel(sql_type = BigInt)]
+pub struct Isbn(i64);
+
+impl Isbn {
+    /// Checks that `isbn13` has the `978` or `979` prefix and the right check
+    /// digit.
+    pub fn new(isbn13: i64) -> Result<Self, Error> {
+        let isbn = Self(isbn13);
+        let digits = isbn.digits();
+        if (9_780_000_000_000..=9_799_999_999_999).contains(&isbn13)
+            && check_digit13(&digits[..12]) == digits[12]
+        {
+            Ok(isbn)
+        } else {
+            Err(invalid(&isbn13.to_string()))
+        }
+    }
+
+    /// Parses an ISBN that refers to a stored book: anything [`FromStr`]
+    /// accepts, or else the plain number of a book stored before ISBNs were
+    /// validated.
+    pub fn parse_stored(s: &str) -> Result<Self, Error> {
+        s.parse()
+            .or_else(|e| s.trim().parse().map(Self).map_err(|_| e))
+    }
+
+    /// Whether the number has the `978` or `979` prefix and the right check
+    /// digit, which is not the case for some books stored before ISBNs were
+    /// validated.
+    pub fn is_valid(self) -> bool {
+        Self::new(self.0).is_ok()
+    }
+
+    /// The ISBN-10 form, with `X` for a check digit of 10, which only
+    /// exists for the `978` prefix.
+    pub fn to_isbn10(self) -> Option<String> {
+        let digits = self.digits();
+        if digits[..3] != [9, 7, 8] {
+            return None;
+        }
+        let mut isbn10: String = digits[3..12].iter().map(|d| char::from(b'0' + d)).collect();
+        isbn10.push(match check_digit10(&digits[3..12]) {
+            10 => 'X',
+            d => char::from(b'0' + d),
+        });
+        Some(isbn10)
+    }
+
+    /// Separates prefix, registration group, registrant, publication and
+    /// check digit, as in `978-0-7475-4215-5`.
+    ///
+    /// Registrant ranges are only known for the registration groups of the
+    /// catalog's languages; ISBNs from other groups are left unhyphenated.
+    pub fn hyphenated(self) -> String {
+        let s = self.0.to_string();
+        if s.len() != 13 {
+            return s;
+        }
+        let range_len = |ranges: &[(u32, usize)], digits: &str| {
+            let value: u32 = format!("{digits:0<7.7}").parse().ok()?;
+            let &(_, len) = ranges.iter().find(|(last, _)| value <= *last)?;
+            (len > 0).then_some(len)
+        };
+        let (prefix, rest) = s.split_at(3);
+        let hyphenated = GROUPS
+            .iter()
+            .find(|(group_prefix, _)| *group_prefix == prefix)
+            .and_then(|(_, groups)| {
+                let group = &rest[..range_len(groups, rest)?];
+                let rest = &rest[group.len()..rest.len() - 1];
+                let (_, registrants) = REGISTRANTS
+                    .iter()
+                    .find(|(name, _)| *name == format!("{prefix}-{group}"))?;
+                let registrant = &rest[..range_len(registrants, rest)?.min(rest.len())];
+                let publication = &rest[registrant.len()..];
+                let check = &s[12..];
+                Some(if publication.is_empty() {
+                    format!("{prefix}-{group}-{registrant}-{check}")
+                } else {
+                    format!("{prefix}-{group}-{registrant}-{publication}-{check}")
+                })
+            });
+        hyphenated.unwrap_or(s)
+    }
+
+    fn digits(self) -> [u8; 13] {
+        let mut digits = [0; 13];
+        let mut n = self.0;
+        for digit in digits.iter_mut().rev() {
+            *digit = (n % 10) as u8;
+            n /= 10;
+        }
+        digits
+    }
+}
+
+fn check_digit13(first12: &[u8]) -> u8 {
+    let sum: u32 = first12
+        .iter()
+        .enumerate()
+        .map(|(i, &d)| u32::from(d) * if i % 2 == 0 { 1 } else { 3 })
+        .sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+/// 10 stands for `X`.
+fn check_digit10(first9: &[u8]) -> u8 {
+    let sum: u32 = first9
+        .iter()
+        .enumerate()
+        .map(|(i, &d)| (10 - i as u32) * u32::from(d))
+        .sum();
+    ((11 - sum % 11) % 11) as u8
+}
+
+fn invalid(s: &str) -> Error {
+    Error::InvalidInput(format!("`{s}` is not a valid ISBN"))
+}
+
+impl FromStr for Isbn {
+    type Err = Error;
+
+    /// Accepts ISBN-13 and ISBN-10 with or without hyphens or spaces and an
+    /// `ISBN` or `urn:isbn:` prefix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let lower = trimmed.to_ascii_lowercase();
+        let id = ["urn:isbn:", "isbn:", "isbn"]
+            .iter()
+            .find_map(|prefix| lower.starts_with(prefix).then(|| &trimmed[prefix.len()..]))
+            .unwrap_or(trimmed);
+        let mut digits = Vec::with_capacity(13);
+        for (i, c) in id.trim().chars().enumerate() {
+            match c {
+                '0'..='9' => digits.push(c as u8 - b'0'),
+                // only as the check digit of an ISBN-10
+                'X' | 'x' if digits.len() == 9 => digits.push(10),
+                '-' | ' ' if i > 0 => {}
+                _ => return Err(invalid(s)),
+            }
+        }
+        let digits = match digits.len() {
+            13 => digits,
+            10 if check_digit10(&digits[..9]) == digits[9] => {
+                let mut isbn13 = vec![9, 7, 8];
+                isbn13.extend_from_slice(&digits[..9]);
+                isbn13.push(check_digit13(&isbn13));
+                isbn13
+            }
+            _ => return Err(invalid(s)),
+        };
+        let isbn = digits.iter().fold(0, |isbn, &d| isbn * 10 + i64::from(d));
+        Self::new(isbn).map_err(|_| invalid(s))
+    }
+}
+
+/// The 13 digits without hyphens; see [`Isbn::hyphenated`].
+impl fmt::Display for Isbn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<Isbn> for i64 {
+    fn from(isbn: Isbn) -> Self {
+        isbn.0
+    }
+}
+
+impl TryFrom<i64> for Isbn {
+    type Error = Error;
+
+    fn try_from(isbn13: i64) -> Result<Self, Self::Error> {
+        Self::new(isbn13)
+    }
+}
+
+impl ToSql<BigInt, Pg> for Isbn {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        <i64 as ToSql<BigInt, Pg>>::to_sql(&self.0, out)
+    }
+}
+
+impl FromSql<BigInt, Pg> for Isbn {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        <i64 as FromSql<BigInt, Pg>>::from_sql(bytes).map(Self)
+    }
+}
+
+impl Serialize for Isbn {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Isbn {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = Isbn;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an ISBN-13 or ISBN-10")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Isbn, E> {
+                Ok(Isbn(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Isbn, E> {
+                i64::try_from(v)
+                    .map(Isbn)
+                    .map_err(|_| E::custom(invalid(&v.to_string())))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Isbn, E> {
+                Isbn::parse_stored(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl<'a, C: Context> Readable<'a, C> for Isbn {
+    fn read_from<R: Reader<'a, C>>(reader: &mut R) -> Result<Self, C::Error> {
+        Ok(Self(reader.read_i64()?))
+    }
+
+    fn minimum_bytes_needed() -> usize {
+        8
+    }
+}
+
+impl<C: Context> Writable<C> for Isbn {
+    fn write_to<T: ?Sized + Writer<C>>(&self, writer: &mut T) -> Result<(), C::Error> {
+        writer.write_i64(self.0)
+    }
+
+    fn bytes_needed(&self) -> Result<usize, C::Error> {
+        Ok(8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isbn10_with_x() {
+        let isbn: Isbn = "0-8044-2957-X".parse().unwrap();
+        assert_eq!(i64::from(isbn), 9_780_804_429_573);
+        assert_eq!(isbn.to_isbn10().unwrap(), "080442957X");
+        assert_eq!("080442957x".parse::<Isbn>().unwrap(), isbn);
+        // `X` is only a check digit
+        assert!("08044295X7".parse::<Isbn>().is_err());
+    }
+
+    #[test]
+    fn prefix_979_has_no_isbn10() {
+        let isbn: Isbn = "979-10-300000-1-6".parse().unwrap();
+        assert_eq!(isbn.to_isbn10(), None);
+        // no registrant ranges for the group
+        assert_eq!(isbn.hyphenated(), "9791030000016");
+    }
+
+    #[test]
+    fn hyphenation_of_each_group() {
+        for hyphenated in [
+            "978-0-7475-4215-5",
+            "978-1-86197-876-9",
+            "978-3-16-148410-0",
+            "978-4-06-204365-6",
+            "978-5-17-090630-7",
+            "978-7-5327-0001-1",
+            "978-966-03-0001-9",
+        ] {
+            let isbn: Isbn = hyphenated.replace('-', "").parse().unwrap();
+            assert_eq!(isbn.hyphenated(), hyphenated);
+        }
+    }
+
+    #[test]
+    fn bad_check_digit() {
+        assert!("978-0-7475-4215-4".parse::<Isbn>().is_err());
+        assert!("0-7475-4215-X".parse::<Isbn>().is_err());
+        assert!(Isbn::new(9_780_747_542_154).is_err());
+    }
+
+    #[test]
+    fn stored_numbers_stay_readable() {
+        let isbn = Isbn::parse_stored("1234").unwrap();
+        assert!(!isbn.is_valid());
+        assert_eq!(i64::from(isbn), 1234);
+        assert_eq!(
+            Isbn::read_from_buffer(&1234_i64.write_to_vec().unwrap()).unwrap(),
+            isbn
+        );
+        assert_eq!(serde_json::from_str::<Isbn>("1234").unwrap(), isbn);
+        assert!(Isbn::parse_stored("not an isbn").is_err());
+        assert!("1234".parse::<Isbn>().is_err());
+    }
+}
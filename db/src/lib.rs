@@ -1,10 +1,40 @@
+use blob::{BlobStore, LocalStore};
 use diesel::{pg::PgConnection, prelude::*};
-use models::{Book, NewBook, NewReview, Rating, Review};
-use schema::{books, reviews};
+pub use error::Error;
+use filter::BookFilter;
+use models::{
+    Author, AuthorName, Book, BookChanges, Contributor, ContributorRole, DeletePolicy, Isbn,
+    Language, NewAuthorName, NewBook, NewContributor, NewReview, NewSeries, NewSeriesEntry,
+    NewWork, Rating, Review, Series, SeriesEntry, Volume, Work,
+};
+use pagination::{BookSortKey, Page, PageRequest, ReviewSortKey};
+use schema::{books, languages, reviews};
+use search::SearchHit;
 use std::{env, time::SystemTime};
 
+pub mod blob;
+pub mod contributors;
+pub mod error;
+pub mod export;
+pub mod filter;
+pub mod import;
+mod isbn;
+pub mod marc;
+pub mod metadata;
 pub mod models;
+pub mod pagination;
 pub mod schema;
+pub mod search;
+pub mod series;
+pub mod storage;
+pub mod validation;
+pub mod works;
+
+diesel::define_sql_function! {
+    /// The search key of an author's name, as stored in `authors` and
+    /// `author_names`.
+    fn name_key(name: diesel::sql_types::Text) -> diesel::sql_types::Text;
+}
 
 pub fn establish_connection() -> PgConnection {
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -12,75 +42,424 @@ pub fn establish_connection() -> PgConnection {
         .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
 }
 
-pub fn create_book(
+/// Opens the blob store selected by `BLOB_STORE`: `local` (the default) keeps
+/// files under `BLOB_DIR`, `s3` uses the bucket described by the `S3_*`
+/// variables.
+pub fn establish_blob_store() -> Box<dyn BlobStore> {
+    match env::var("BLOB_STORE").as_deref().unwrap_or("local") {
+        "local" => Box::new(LocalStore::new(
+            env::var("BLOB_DIR").unwrap_or_else(|_| ".".into()),
+        )),
+        #[cfg(feature = "s3")]
+        "s3" => {
+            let var = |name| env::var(name).unwrap_or_else(|_| panic!("{name} must be set"));
+            Box::new(blob::S3Store::new(
+                &var("S3_ENDPOINT"),
+                &var("S3_BUCKET"),
+                &env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".into()),
+                &var("S3_ACCESS_KEY_ID"),
+                &var("S3_SECRET_ACCESS_KEY"),
+            ))
+        }
+        #[cfg(not(feature = "s3"))]
+        "s3" => panic!("BLOB_STORE=s3 requires building with the `s3` feature"),
+        other => panic!("unknown BLOB_STORE `{other}`, expected `local` or `s3`"),
+    }
+}
+
+/// Fails with [`Error::Validation`] if [`validation::check_book`] finds
+/// problems or the language is not in the `languages` table.
+///
+/// The authors are recorded as contributors, split from the byline with
+/// [`contributors::split_names`].
+pub fn create_book(conn: &mut PgConnection, book: &NewBook) -> Result<usize, Error> {
+    let mut errors = validation::check_book(book);
+    errors.extend(validation::check_language(conn, &book.language)?);
+    validation::ensure_valid(errors)?;
+    conn.transaction(|conn| {
+        let inserted = diesel::insert_into(books::table)
+            .values(book)
+            .execute(conn)?;
+        contributors::set_authors(conn, book.isbn, &book.author)?;
+        Ok(inserted)
+    })
+}
+
+/// Deletes or archives a book, handling its reviews according to `policy`.
+///
+/// Returns the number of affected books, which is 0 if there is no such book
+/// (or it is already archived, for [`DeletePolicy::Archive`]).
+pub fn delete_book(
     conn: &mut PgConnection,
-    book: &NewBook,
-) -> Result<usize, diesel::result::Error> {
-    diesel::insert_into(books::table).values(book).execute(conn)
+    isbn: Isbn,
+    policy: DeletePolicy,
+) -> Result<usize, Error> {
+    let book = books::table.filter(books::isbn.eq(isbn));
+    match policy {
+        DeletePolicy::Restrict => conn.transaction(|conn| {
+            if book
+                .select(books::isbn)
+                .for_update()
+                .first::<Isbn>(conn)
+                .optional()?
+                .is_none()
+            {
+                return Ok(0);
+            }
+            let reviews = reviews::table
+                .filter(reviews::isbn.eq(isbn))
+                .count()
+                .get_result::<i64>(conn)?;
+            if reviews > 0 {
+                return Err(Error::HasReviews(reviews));
+            }
+            Ok(diesel::delete(book).execute(conn)?)
+        }),
+        DeletePolicy::Cascade => conn.transaction(|conn| {
+            diesel::delete(reviews::table.filter(reviews::isbn.eq(isbn))).execute(conn)?;
+            Ok(diesel::delete(book).execute(conn)?)
+        }),
+        DeletePolicy::Archive => archive_book(conn, isbn),
+    }
 }
 
-pub fn delete_book(conn: &mut PgConnection, isbn: i64) -> Result<usize, diesel::result::Error> {
-    diesel::delete(books::table.filter(books::isbn.eq(isbn))).execute(conn)
+/// Hides a book from listings and search while keeping it and its reviews.
+///
+/// Returns 0 if there is no such book or it is already archived.
+pub fn archive_book(conn: &mut PgConnection, isbn: Isbn) -> Result<usize, Error> {
+    Ok(diesel::update(
+        books::table
+            .filter(books::isbn.eq(isbn))
+            .filter(books::archived_at.is_null()),
+    )
+    .set(books::archived_at.eq(SystemTime::now()))
+    .execute(conn)?)
 }
 
-pub fn load_books(conn: &mut PgConnection) -> Result<Vec<Book>, diesel::result::Error> {
-    books::table.load::<Book>(conn)
+/// Returns 0 if there is no such book or it is not archived.
+pub fn restore_book(conn: &mut PgConnection, isbn: Isbn) -> Result<usize, Error> {
+    Ok(diesel::update(
+        books::table
+            .filter(books::isbn.eq(isbn))
+            .filter(books::archived_at.is_not_null()),
+    )
+    .set(books::archived_at.eq(None::<SystemTime>))
+    .execute(conn)?)
 }
 
-pub fn get_book(conn: &mut PgConnection, isbn: i64) -> Result<Book, diesel::result::Error> {
-    books::table
-        .filter(books::isbn.eq(isbn))
-        .first::<Book>(conn)
+/// Applies `changes` to the book with the given ISBN and returns the updated row.
+///
+/// The row is locked for the duration of the transaction, so concurrent
+/// partial updates of different fields do not overwrite each other. A changed
+/// byline replaces the authors among the contributors.
+pub fn update_book(
+    conn: &mut PgConnection,
+    isbn: Isbn,
+    changes: &BookChanges,
+) -> Result<Book, Error> {
+    let mut errors = validation::check_book_changes(changes);
+    if let Some(language) = &changes.language {
+        errors.extend(validation::check_language(conn, language)?);
+    }
+    validation::ensure_valid(errors)?;
+    conn.transaction(|conn| {
+        let book = books::table.find(isbn).for_update().first::<Book>(conn)?;
+        if changes.is_empty() {
+            return Ok(book);
+        }
+        let updated = diesel::update(books::table.find(isbn))
+            .set(changes)
+            .get_result::<Book>(conn)?;
+        if updated.author != book.author {
+            // the byline may be rewritten with preferred names
+            contributors::set_authors(conn, isbn, &updated.author)?;
+            return Ok(books::table.find(isbn).first::<Book>(conn)?);
+        }
+        Ok(updated)
+    })
+}
+
+/// Loads every book that is not archived.
+pub fn load_books(conn: &mut PgConnection) -> Result<Vec<Book>, Error> {
+    Ok(books::table
+        .filter(books::archived_at.is_null())
+        .load::<Book>(conn)?)
+}
+
+pub fn find_books(conn: &mut PgConnection, filter: &BookFilter) -> Result<Vec<Book>, Error> {
+    Ok(filter
+        .apply(books::table.into_boxed())
+        .order(books::title)
+        .load::<Book>(conn)?)
 }
 
-pub fn create_review(
+pub fn list_books(
     conn: &mut PgConnection,
-    review: &NewReview,
-) -> Result<usize, diesel::result::Error> {
-    diesel::insert_into(reviews::table)
-        .values(review)
-        .execute(conn)
+    filter: &BookFilter,
+    page: &PageRequest<BookSortKey>,
+) -> Result<Page<Book>, Error> {
+    pagination::books_page(conn, filter.apply(books::table.into_boxed()), page)
+}
+
+/// Fails with [`Error::Validation`] if [`validation::check_work`] finds
+/// problems.
+pub fn create_work(conn: &mut PgConnection, work: &NewWork) -> Result<Work, Error> {
+    works::create(conn, work)
+}
+
+pub fn get_work(conn: &mut PgConnection, id: i32) -> Result<Work, Error> {
+    works::get(conn, id)
+}
+
+pub fn update_work(conn: &mut PgConnection, id: i32, work: &NewWork) -> Result<Work, Error> {
+    works::update(conn, id, work)
+}
+
+/// Deletes a work, keeping its editions as books outside any work.
+pub fn delete_work(conn: &mut PgConnection, id: i32) -> Result<usize, Error> {
+    works::delete(conn, id)
+}
+
+/// Puts a book in a work as one of its editions, or takes it out with `None`.
+pub fn set_work(conn: &mut PgConnection, isbn: Isbn, work_id: Option<i32>) -> Result<Book, Error> {
+    works::set_work(conn, isbn, work_id)
+}
+
+/// Editions of a work in circulation, oldest first.
+pub fn get_editions(conn: &mut PgConnection, id: i32) -> Result<Vec<Book>, Error> {
+    works::editions(conn, id)
+}
+
+/// Editions in circulation of several works at once, by work and then oldest
+/// first.
+pub fn get_editions_of_works(conn: &mut PgConnection, ids: &[i32]) -> Result<Vec<Book>, Error> {
+    works::editions_of_works(conn, ids)
+}
+
+/// The other editions in circulation of the work a book belongs to, such as
+/// its translations.
+pub fn other_editions(conn: &mut PgConnection, isbn: Isbn) -> Result<Vec<Book>, Error> {
+    works::other_editions(conn, isbn)
+}
+
+/// Fails with [`Error::Validation`] if the title is empty or too long, and
+/// with [`Error::UniqueViolation`] if a series has the same title.
+pub fn create_series(conn: &mut PgConnection, new: &NewSeries) -> Result<Series, Error> {
+    series::create(conn, new)
+}
+
+pub fn get_series(conn: &mut PgConnection, id: i32) -> Result<Series, Error> {
+    series::get(conn, id)
+}
+
+/// Every series, by title.
+pub fn load_series(conn: &mut PgConnection) -> Result<Vec<Series>, Error> {
+    series::load(conn)
+}
+
+pub fn rename_series(conn: &mut PgConnection, id: i32, new: &NewSeries) -> Result<Series, Error> {
+    series::rename(conn, id, new)
+}
+
+/// Deletes a series, keeping its books.
+pub fn delete_series(conn: &mut PgConnection, id: i32) -> Result<usize, Error> {
+    series::delete(conn, id)
+}
+
+/// The books of a series in circulation in reading order.
+pub fn get_volumes(conn: &mut PgConnection, id: i32) -> Result<Vec<Volume>, Error> {
+    series::volumes(conn, id)
+}
+
+/// The series a book belongs to with its volume number in each.
+pub fn get_book_series(conn: &mut PgConnection, isbn: Isbn) -> Result<Vec<SeriesEntry>, Error> {
+    series::get_for_book(conn, isbn)
+}
+
+/// Replaces the series a book belongs to, adding series with new titles.
+///
+/// Fails with [`Error::Validation`] if [`validation::check_series_entries`]
+/// finds problems.
+pub fn set_book_series(
+    conn: &mut PgConnection,
+    isbn: Isbn,
+    entries: &[NewSeriesEntry],
+) -> Result<Vec<SeriesEntry>, Error> {
+    series::set_for_book(conn, isbn, entries)
 }
 
-pub fn get_reviews_by_book(
+/// Full-text search over titles, authors and descriptions of the books
+/// selected by the filter, best matches first.
+pub fn search_books(
     conn: &mut PgConnection,
-    isbn: i64,
-) -> Result<Vec<Review>, diesel::result::Error> {
-    reviews::table
+    query: &str,
+    filter: &BookFilter,
+    limit: i64,
+) -> Result<Vec<SearchHit>, Error> {
+    search::search_books(conn, query, filter, limit)
+}
+
+/// The people credited on a book, in order.
+pub fn get_contributors(conn: &mut PgConnection, isbn: Isbn) -> Result<Vec<Contributor>, Error> {
+    contributors::get(conn, isbn)
+}
+
+/// Replaces the people credited on a book and rewrites its byline from the
+/// preferred names of the ones in the author role.
+///
+/// Fails with [`Error::Validation`] if [`validation::check_contributors`]
+/// finds problems.
+pub fn set_contributors(
+    conn: &mut PgConnection,
+    isbn: Isbn,
+    contributors: &[NewContributor],
+) -> Result<Vec<Contributor>, Error> {
+    contributors::set(conn, isbn, contributors)
+}
+
+pub fn get_author(conn: &mut PgConnection, id: i32) -> Result<Author, Error> {
+    contributors::get_author(conn, id)
+}
+
+/// Fails with [`Error::ForeignKeyViolation`] if the author is still credited
+/// on a book.
+pub fn delete_author(conn: &mut PgConnection, id: i32) -> Result<usize, Error> {
+    contributors::delete_author(conn, id)
+}
+
+/// Authors with a preferred or alternate name that contains `query`, in any
+/// spelling or transliteration, by preferred name.
+pub fn find_authors(
+    conn: &mut PgConnection,
+    query: &str,
+    limit: i64,
+) -> Result<Vec<Author>, Error> {
+    contributors::find_authors(conn, query, limit)
+}
+
+/// The alternate names of an author, without the preferred one.
+pub fn get_author_names(conn: &mut PgConnection, id: i32) -> Result<Vec<AuthorName>, Error> {
+    contributors::get_names(conn, id)
+}
+
+/// Fails with [`Error::InvalidInput`] if the name is the preferred name of an
+/// author, which should be merged instead, and with
+/// [`Error::UniqueViolation`] if it already belongs to an author.
+pub fn add_author_name(
+    conn: &mut PgConnection,
+    id: i32,
+    name: &NewAuthorName,
+) -> Result<AuthorName, Error> {
+    contributors::add_name(conn, id, name)
+}
+
+pub fn remove_author_name(conn: &mut PgConnection, id: i32, name: &str) -> Result<usize, Error> {
+    contributors::remove_name(conn, id, name)
+}
+
+/// Makes `name` the preferred name, keeping the old one as an alternate name,
+/// and rewrites the bylines of the author's books.
+pub fn set_preferred_name(conn: &mut PgConnection, id: i32, name: &str) -> Result<Author, Error> {
+    contributors::set_preferred_name(conn, id, name)
+}
+
+/// Merges two records of the same person: the contributions and names of
+/// `from` move to `into`, and `from` is deleted.
+pub fn merge_authors(conn: &mut PgConnection, into: i32, from: i32) -> Result<Author, Error> {
+    contributors::merge(conn, into, from)
+}
+
+/// Books in circulation that credit the author, in any role or only in
+/// `role`, oldest first.
+pub fn books_by_contributor(
+    conn: &mut PgConnection,
+    author_id: i32,
+    role: Option<ContributorRole>,
+) -> Result<Vec<Book>, Error> {
+    contributors::books_by_contributor(conn, author_id, role)
+}
+
+/// The languages books can be written in, by English name.
+pub fn load_languages(conn: &mut PgConnection) -> Result<Vec<Language>, Error> {
+    Ok(languages::table
+        .select((
+            languages::code,
+            languages::bibliographic_code,
+            languages::terminology_code,
+            languages::english_name,
+            languages::native_name,
+        ))
+        .order(languages::english_name)
+        .load::<Language>(conn)?)
+}
+
+pub fn get_book(conn: &mut PgConnection, isbn: Isbn) -> Result<Book, Error> {
+    Ok(books::table
+        .filter(books::isbn.eq(isbn))
+        .first::<Book>(conn)?)
+}
+
+/// Fails with [`Error::Validation`] if [`validation::check_review`] finds
+/// problems, including a missing book.
+pub fn create_review(conn: &mut PgConnection, review: &NewReview) -> Result<usize, Error> {
+    validation::ensure_valid(validation::check_review(conn, review)?)?;
+    Ok(diesel::insert_into(reviews::table)
+        .values(review)
+        .execute(conn)?)
+}
+
+pub fn get_reviews_by_book(conn: &mut PgConnection, isbn: Isbn) -> Result<Vec<Review>, Error> {
+    Ok(reviews::table
         .filter(reviews::isbn.eq(isbn))
-        .load::<Review>(conn)
+        .load::<Review>(conn)?)
+}
+
+/// Reviews of a book, or with `editions` of every edition of the work it
+/// belongs to.
+pub fn list_reviews_by_book(
+    conn: &mut PgConnection,
+    isbn: Isbn,
+    editions: bool,
+    page: &PageRequest<ReviewSortKey>,
+) -> Result<Page<Review>, Error> {
+    pagination::reviews_by_book_page(conn, isbn, editions, page)
 }
 
 pub fn get_reviews_by_username(
     conn: &mut PgConnection,
     username: &str,
-) -> Result<Vec<Review>, diesel::result::Error> {
-    reviews::table
+) -> Result<Vec<Review>, Error> {
+    Ok(reviews::table
         .filter(reviews::username.eq(username))
-        .load::<Review>(conn)
+        .load::<Review>(conn)?)
+}
+
+pub fn list_reviews_by_username(
+    conn: &mut PgConnection,
+    username: &str,
+    page: &PageRequest<ReviewSortKey>,
+) -> Result<Page<Review>, Error> {
+    pagination::reviews_by_username_page(conn, username, page)
 }
 
 pub fn update_review(
     conn: &mut PgConnection,
-    isbn: i64,
+    isbn: Isbn,
     username: &str,
     description: &str,
     rating: Rating,
     updated_at: SystemTime,
-) -> Result<usize, diesel::result::Error> {
-    diesel::update(reviews::table.find((isbn, username)))
+) -> Result<usize, Error> {
+    validation::ensure_valid(validation::check_review_text(description))?;
+    Ok(diesel::update(reviews::table.find((isbn, username)))
         .set((
             reviews::description.eq(description),
             reviews::rating.eq(rating),
             reviews::updated_at.eq(updated_at),
         ))
-        .execute(conn)
+        .execute(conn)?)
 }
 
-pub fn delete_review(
-    conn: &mut PgConnection,
-    isbn: i64,
-    username: &str,
-) -> Result<usize, diesel::result::Error> {
-    diesel::delete(reviews::table.find((isbn, username))).execute(conn)
+pub fn delete_review(conn: &mut PgConnection, isbn: Isbn, username: &str) -> Result<usize, Error> {
+    Ok(diesel::delete(reviews::table.find((isbn, username))).execute(conn)?)
 }
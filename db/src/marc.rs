@@ -0,0 +1,440 @@
+//! MARC 21 bibliographic records, in ISO 2709 (binary MARC) and MARCXML.
+//!
+//! Only Unicode binary records (leader/09 `a`) are read; MARC-8 ones are
+//! reported as unreadable rather than decoded wrongly.
+
+use crate::Error;
+use std::fmt::Write as _;
+
+pub const MARCXML_NAMESPACE: &str = "http://www.loc.gov/MARC21/slim";
+
+const FIELD_TERMINATOR: u8 = 0x1e;
+const RECORD_TERMINATOR: u8 = 0x1d;
+const SUBFIELD_DELIMITER: u8 = 0x1f;
+const LEADER_LEN: usize = 24;
+/// Field lengths have four digits in the directory.
+const MAX_FIELD_LEN: usize = 9_999;
+/// Record length and base address have five digits.
+const MAX_LEN: usize = 99_999;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// 24 characters; record length and base address are filled in when
+    /// the record is written.
+    pub leader: String,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// Tags `001` to `009`.
+    Control { tag: String, value: String },
+    Data {
+        tag: String,
+        indicators: [char; 2],
+        subfields: Vec<(char, String)>,
+    },
+}
+
+impl Field {
+    pub fn tag(&self) -> &str {
+        match self {
+            Self::Control { tag, .. } | Self::Data { tag, .. } => tag,
+        }
+    }
+
+    /// Values of the subfields with the given code, none for control fields.
+    pub fn subfields(&self, code: char) -> impl Iterator<Item = &str> {
+        let subfields = match self {
+            Self::Control { .. } => &[][..],
+            Self::Data { subfields, .. } => subfields,
+        };
+        subfields
+            .iter()
+            .filter(move |(c, _)| *c == code)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Record {
+    /// Leader of a Unicode record describing a book (language material,
+    /// monograph).
+    pub const BOOK_LEADER: &'static str = "00000nam a2200000   4500";
+
+    pub fn new(leader: &str) -> Self {
+        Self {
+            leader: leader.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn control(mut self, tag: &str, value: impl Into<String>) -> Self {
+        self.fields.push(Field::Control {
+            tag: tag.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Leaves out subfields with empty values, and the whole field if none
+    /// is left.
+    pub fn data(mut self, tag: &str, indicators: [char; 2], subfields: &[(char, &str)]) -> Self {
+        let subfields: Vec<(char, String)> = subfields
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|&(code, value)| (code, value.into()))
+            .collect();
+        if !subfields.is_empty() {
+            self.fields.push(Field::Data {
+                tag: tag.into(),
+                indicators,
+                subfields,
+            });
+        }
+        self
+    }
+
+    pub fn fields<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a Field> {
+        self.fields.iter().filter(move |field| field.tag() == tag)
+    }
+
+    pub fn control_field(&self, tag: &str) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            Field::Control { tag: t, value } if t == tag => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Values of the given subfield in every field with the given tag.
+    pub fn subfields<'a>(&'a self, tag: &'a str, code: char) -> impl Iterator<Item = &'a str> {
+        self.fields(tag)
+            .flat_map(move |field| field.subfields(code))
+    }
+
+    /// Fails with [`Error::InvalidInput`] if the record or one of its fields
+    /// is longer than ISO 2709 allows.
+    pub fn to_iso2709(&self) -> Result<Vec<u8>, Error> {
+        let mut directory = String::new();
+        let mut data = Vec::new();
+        for field in &self.fields {
+            let start = data.len();
+            match field {
+                Field::Control { value, .. } => data.extend_from_slice(value.as_bytes()),
+                Field::Data {
+                    indicators,
+                    subfields,
+                    ..
+                } => {
+                    for indicator in indicators {
+                        data.extend_from_slice(indicator.encode_utf8(&mut [0; 4]).as_bytes());
+                    }
+                    for (code, value) in subfields {
+                        data.push(SUBFIELD_DELIMITER);
+                        data.extend_from_slice(code.encode_utf8(&mut [0; 4]).as_bytes());
+                        data.extend_from_slice(value.as_bytes());
+                    }
+                }
+            }
+            data.push(FIELD_TERMINATOR);
+            let field_len = data.len() - start;
+            if field_len > MAX_FIELD_LEN {
+                return Err(Error::InvalidInput(format!(
+                    "MARC field {} of {field_len} bytes is too long",
+                    field.tag()
+                )));
+            }
+            let _ = write!(directory, "{:0>3.3}{field_len:04}{start:05}", field.tag());
+        }
+        data.push(RECORD_TERMINATOR);
+
+        let base_address = LEADER_LEN + directory.len() + 1;
+        let len = base_address + data.len();
+        if len > MAX_LEN {
+            return Err(Error::InvalidInput(format!(
+                "MARC record of {len} bytes is too long"
+            )));
+        }
+        let mut leader = format!("{:<24.24}", self.leader).into_bytes();
+        leader[..5].copy_from_slice(format!("{len:05}").as_bytes());
+        leader[12..17].copy_from_slice(format!("{base_address:05}").as_bytes());
+
+        let mut record = Vec::with_capacity(len);
+        record.extend_from_slice(&leader);
+        record.extend_from_slice(directory.as_bytes());
+        record.push(FIELD_TERMINATOR);
+        record.extend_from_slice(&data);
+        Ok(record)
+    }
+
+    /// Appends the record as a MARCXML `<record>` element, to be wrapped in a
+    /// `<collection>` in the [`MARCXML_NAMESPACE`].
+    pub fn write_xml(&self, out: &mut String) {
+        out.push_str("<record>");
+        let _ = write!(out, "<leader>{}</leader>", escape(&self.leader));
+        for field in &self.fields {
+            match field {
+                Field::Control { tag, value } => {
+                    let _ = write!(
+                        out,
+                        r#"<controlfield tag="{}">{}</controlfield>"#,
+                        escape(tag),
+                        escape(value)
+                    );
+                }
+                Field::Data {
+                    tag,
+                    indicators: [ind1, ind2],
+                    subfields,
+                } => {
+                    let _ = write!(
+                        out,
+                        r#"<datafield tag="{}" ind1="{}" ind2="{}">"#,
+                        escape(tag),
+                        escape(&ind1.to_string()),
+                        escape(&ind2.to_string())
+                    );
+                    for (code, value) in subfields {
+                        let _ = write!(
+                            out,
+                            r#"<subfield code="{}">{}</subfield>"#,
+                            escape(&code.to_string()),
+                            escape(value)
+                        );
+                    }
+                    out.push_str("</datafield>");
+                }
+            }
+        }
+        out.push_str("</record>\n");
+    }
+}
+
+/// Reads consecutive ISO 2709 records, each of which may be unreadable on
+/// its own.
+pub fn read_iso2709(data: &[u8]) -> Vec<Result<Record, String>> {
+    data.split(|&b| b == RECORD_TERMINATOR)
+        .filter(|record| !record.iter().all(u8::is_ascii_whitespace))
+        .map(parse_iso2709)
+        .collect()
+}
+
+fn parse_iso2709(data: &[u8]) -> Result<Record, String> {
+    // records may be separated by line breaks
+    let data = data.trim_ascii_start();
+    let number = |bytes: &[u8]| -> Option<usize> { std::str::from_utf8(bytes).ok()?.parse().ok() };
+    let leader = data
+        .get(..LEADER_LEN)
+        .ok_or("record is shorter than its leader")?;
+    if leader[9] != b'a' {
+        return Err(format!(
+            "record is not in Unicode (leader/09 is `{}`), MARC-8 is not supported",
+            char::from(leader[9])
+        ));
+    }
+    let base_address = number(&leader[12..17]).ok_or("leader has no base address")?;
+    let directory = data
+        .get(LEADER_LEN..base_address.saturating_sub(1))
+        .ok_or("directory is out of bounds")?;
+    let mut record = Record::new(&String::from_utf8_lossy(leader));
+    for entry in directory.chunks(12) {
+        let (Ok(tag), Some(len), Some(start)) = (
+            std::str::from_utf8(&entry[..3.min(entry.len())]),
+            entry.get(3..7).and_then(number),
+            entry.get(7..12).and_then(number),
+        ) else {
+            return Err("invalid directory entry".into());
+        };
+        let content = data
+            .get(base_address + start..base_address + start + len)
+            .ok_or_else(|| format!("field {tag} is out of bounds"))?;
+        let content = content.strip_suffix(&[FIELD_TERMINATOR]).unwrap_or(content);
+        let content = String::from_utf8_lossy(content);
+        if tag.starts_with("00") {
+            record = record.control(tag, content);
+        } else {
+            let mut parts = content.split(char::from(SUBFIELD_DELIMITER));
+            let mut indicators = parts.next().unwrap_or_default().chars();
+            let indicators = [
+                indicators.next().unwrap_or(' '),
+                indicators.next().unwrap_or(' '),
+            ];
+            let subfields = parts
+                .filter_map(|part| {
+                    let mut chars = part.chars();
+                    Some((chars.next()?, chars.as_str().to_owned()))
+                })
+                .collect();
+            record.fields.push(Field::Data {
+                tag: tag.into(),
+                indicators,
+                subfields,
+            });
+        }
+    }
+    Ok(record)
+}
+
+/// Reads the `<record>` elements of a MARCXML document, which may be a
+/// single record or a `<collection>`.
+pub fn read_xml(text: &str) -> Result<Vec<Record>, Error> {
+    let doc = roxmltree::Document::parse(text)
+        .map_err(|e| Error::InvalidInput(format!("unreadable MARCXML: {e}")))?;
+    let named = |node: &roxmltree::Node, name| node.is_element() && node.tag_name().name() == name;
+    Ok(doc
+        .descendants()
+        .filter(|node| named(node, "record"))
+        .map(|node| {
+            let mut record = Record::new(
+                node.children()
+                    .find(|child| named(child, "leader"))
+                    .and_then(|leader| leader.text())
+                    .unwrap_or_default(),
+            );
+            for child in node.children() {
+                let tag = child.attribute("tag").unwrap_or_default();
+                if named(&child, "controlfield") {
+                    record = record.control(tag, child.text().unwrap_or_default());
+                } else if named(&child, "datafield") {
+                    let indicator = |name| {
+                        child
+                            .attribute(name)
+                            .and_then(|value: &str| value.chars().next())
+                            .unwrap_or(' ')
+                    };
+                    let subfields = child
+                        .children()
+                        .filter(|subfield| named(subfield, "subfield"))
+                        .filter_map(|subfield| {
+                            let code = subfield.attribute("code")?.chars().next()?;
+                            Some((code, subfield.text().unwrap_or_default().to_owned()))
+                        })
+                        .collect();
+                    record.fields.push(Field::Data {
+                        tag: tag.into(),
+                        indicators: [indicator("ind1"), indicator("ind2")],
+                        subfields,
+                    });
+                }
+            }
+            record
+        })
+        .collect())
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            // not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> Record {
+        Record::new(Record::BOOK_LEADER)
+            .control("001", "9780747542155")
+            .control("008", "970626s1997    enk           000 1 eng d")
+            .data("020", [' ', ' '], &[('a', "9780747542155")])
+            .data("100", ['1', ' '], &[('a', "Rowling, J. K.")])
+            .data(
+                "245",
+                ['1', '0'],
+                &[
+                    ('a', "Harry Potter & the <stone> /"),
+                    ('c', "J. K. Rowling"),
+                ],
+            )
+            .data("520", [' ', ' '], &[('a', "Первая книга серии.")])
+    }
+
+    /// The leader as written, with the record length and base address.
+    fn written_leader(record: &Record) -> String {
+        String::from_utf8(record.to_iso2709().unwrap()[..LEADER_LEN].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn iso2709_round_trip() {
+        let first = book();
+        let second = Record::new(Record::BOOK_LEADER)
+            .control("001", "second")
+            .data("245", ['0', '0'], &[('a', "Second"), ('b', "a subtitle")]);
+        let mut data = first.to_iso2709().unwrap();
+        // records may be separated by line breaks
+        data.push(b'\n');
+        data.extend(second.to_iso2709().unwrap());
+
+        let read: Vec<Record> = read_iso2709(&data)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read.len(), 2);
+        for (read, written) in read.iter().zip([&first, &second]) {
+            assert_eq!(read.leader, written_leader(written));
+            assert_eq!(read.fields, written.fields);
+        }
+    }
+
+    #[test]
+    fn iso2709_too_long() {
+        let long = "x".repeat(MAX_FIELD_LEN);
+        let record = Record::new(Record::BOOK_LEADER).data("520", [' ', ' '], &[('a', &long)]);
+        assert!(matches!(record.to_iso2709(), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn iso2709_skips_unreadable_records() {
+        let mut marc8 = book().to_iso2709().unwrap();
+        marc8[9] = b' ';
+        let mut data = b"garbage".to_vec();
+        data.push(RECORD_TERMINATOR);
+        data.extend(marc8);
+        data.extend(book().to_iso2709().unwrap());
+
+        let read = read_iso2709(&data);
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[0], Err("record is shorter than its leader".into()));
+        assert!(read[1].as_ref().unwrap_err().contains("leader/09"));
+        assert_eq!(read[2].as_ref().unwrap().fields, book().fields);
+    }
+
+    #[test]
+    fn marcxml_round_trip() {
+        let records = [book(), Record::new(Record::BOOK_LEADER).control("001", "x")];
+        let mut xml = format!(r#"<collection xmlns="{MARCXML_NAMESPACE}">"#);
+        for record in &records {
+            record.write_xml(&mut xml);
+        }
+        xml.push_str("</collection>");
+        assert!(xml.contains("Harry Potter &amp; the &lt;stone&gt; /"));
+        assert_eq!(read_xml(&xml).unwrap(), records);
+    }
+
+    #[test]
+    fn marcxml_single_record() {
+        let xml = format!(
+            r#"<record xmlns="{MARCXML_NAMESPACE}"><leader>{}</leader>
+                <datafield tag="245" ind1="1" ind2="0">
+                    <subfield code="a">Title</subfield>
+                </datafield>
+            </record>"#,
+            Record::BOOK_LEADER
+        );
+        let read = read_xml(&xml).unwrap();
+        assert_eq!(
+            read,
+            [Record::new(Record::BOOK_LEADER).data("245", ['1', '0'], &[('a', "Title")])]
+        );
+        assert!(matches!(read_xml("<record>"), Err(Error::InvalidInput(_))));
+    }
+}
@@ -0,0 +1,276 @@
+//! Bibliographic metadata embedded in book files, used to prefill new books.
+//!
+//! EPUB files are read from the OPF package document, PDF files from the
+//! document information dictionary and the XMP metadata stream, XMP taking
+//! precedence.
+
+use crate::{models::Isbn, storage::sniff_mime, Error};
+use lopdf::Document;
+use roxmltree::Node;
+use std::{
+    fs,
+    io::{Cursor, Read},
+    path::Path,
+};
+use zip::ZipArchive;
+
+const DC: &str = "http://purl.org/dc/elements/1.1/";
+const PRISM: &str = "http://prismstandard.org/namespaces/basic/2.0/";
+
+/// What could be found in a file; each field is `None` if it was missing or
+/// unusable.
+#[derive(Debug, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    /// All creators, separated by `, `.
+    pub author: Option<String>,
+    pub description: Option<String>,
+    /// A language tag like `en-US`, for [`crate::models::Language::find`].
+    pub language: Option<String>,
+    pub issue_year: Option<i32>,
+    pub isbn: Option<Isbn>,
+    /// The cover image, only found in EPUB files.
+    pub cover: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// Fills the fields that are still `None` from `other`.
+    fn or(self, other: Self) -> Self {
+        Self {
+            title: self.title.or(other.title),
+            author: self.author.or(other.author),
+            description: self.description.or(other.description),
+            language: self.language.or(other.language),
+            issue_year: self.issue_year.or(other.issue_year),
+            isbn: self.isbn.or(other.isbn),
+            cover: self.cover.or(other.cover),
+        }
+    }
+}
+
+pub fn extract_file(path: &Path) -> Result<Metadata, Error> {
+    extract(&fs::read(path)?)
+}
+
+/// Fails with [`Error::InvalidInput`] if `data` is neither a readable EPUB
+/// nor a readable PDF file.
+pub fn extract(data: &[u8]) -> Result<Metadata, Error> {
+    match sniff_mime(data) {
+        // some EPUB files do not start with the `mimetype` entry
+        "application/epub+zip" | "application/zip" => epub(data),
+        "application/pdf" => pdf(data),
+        mime => Err(Error::InvalidInput(format!(
+            "cannot read metadata from {mime} files"
+        ))),
+    }
+}
+
+fn epub(data: &[u8]) -> Result<Metadata, Error> {
+    let invalid = |e: &dyn std::fmt::Display| Error::InvalidInput(format!("unreadable EPUB: {e}"));
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(|e| invalid(&e))?;
+    let mut read = |name: &str| -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        archive
+            .by_name(name)
+            .map_err(|e| invalid(&format!("{name}: {e}")))?
+            .read_to_end(&mut bytes)?;
+        Ok(bytes)
+    };
+
+    let container = String::from_utf8_lossy(&read("META-INF/container.xml")?).into_owned();
+    let container = roxmltree::Document::parse(&container).map_err(|e| invalid(&e))?;
+    let opf_path = container
+        .descendants()
+        .find(|node| node.has_tag_name("rootfile"))
+        .and_then(|node| node.attribute("full-path"))
+        .ok_or_else(|| invalid(&"no package document"))?
+        .to_owned();
+    let opf = String::from_utf8_lossy(&read(&opf_path)?).into_owned();
+    let opf = roxmltree::Document::parse(&opf).map_err(|e| invalid(&e))?;
+
+    let mut metadata = dublin_core(opf.root());
+    // a missing or broken cover is no reason to drop the rest
+    metadata.cover = cover_href(&opf)
+        .and_then(|href| read(&resolve(&opf_path, href)).ok())
+        .filter(|cover| sniff_mime(cover).starts_with("image/"));
+    Ok(metadata)
+}
+
+/// Where the cover image is, relative to the package document: the item with
+/// the `cover-image` property in EPUB 3, the one named by
+/// `<meta name="cover">` in EPUB 2.
+fn cover_href<'a>(opf: &'a roxmltree::Document) -> Option<&'a str> {
+    let items: Vec<Node> = opf
+        .descendants()
+        .filter(|node| node.has_tag_name("item"))
+        .collect();
+    let by_property = items.iter().find(|item| {
+        item.attribute("properties")
+            .is_some_and(|properties| properties.split_whitespace().any(|p| p == "cover-image"))
+    });
+    let by_meta = || {
+        let id = opf
+            .descendants()
+            .find(|node| node.has_tag_name("meta") && node.attribute("name") == Some("cover"))?
+            .attribute("content")?;
+        items.iter().find(|item| item.attribute("id") == Some(id))
+    };
+    by_property.or_else(by_meta)?.attribute("href")
+}
+
+/// Joins `href` to the directory of `base`, both being paths inside the
+/// archive.
+fn resolve(base: &str, href: &str) -> String {
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+    for part in href.split('/') {
+        match part {
+            "." | "" => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn pdf(data: &[u8]) -> Result<Metadata, Error> {
+    let doc = Document::load_mem(data)
+        .map_err(|e| Error::InvalidInput(format!("unreadable PDF: {e}")))?;
+    let xmp = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Metadata"))
+        .and_then(|metadata| doc.dereference(metadata))
+        .and_then(|(_, metadata)| metadata.as_stream())
+        .ok()
+        .and_then(|stream| {
+            if stream.dict.has(b"Filter") {
+                stream.decompressed_content().ok()
+            } else {
+                Some(stream.content.clone())
+            }
+        });
+    let xmp = xmp
+        .as_deref()
+        .map(String::from_utf8_lossy)
+        .and_then(|xmp| {
+            roxmltree::Document::parse(&xmp)
+                .ok()
+                .map(|xmp| dublin_core(xmp.root()))
+        })
+        .unwrap_or_default();
+
+    let info = doc
+        .trailer
+        .get(b"Info")
+        .and_then(|info| doc.dereference(info))
+        .and_then(|(_, info)| info.as_dict())
+        .ok();
+    let field = |name: &[u8]| {
+        let value = info?.get(name).ok()?;
+        let (_, value) = doc.dereference(value).ok()?;
+        non_empty(pdf_text(value.as_str().ok()?))
+    };
+    let info = Metadata {
+        title: field(b"Title"),
+        author: field(b"Author"),
+        description: field(b"Subject"),
+        // `D:YYYYMMDDHHmmSS`, often when the file was made rather than
+        // when the book was published
+        issue_year: field(b"CreationDate").as_deref().and_then(parse_year),
+        ..Default::default()
+    };
+    Ok(xmp.or(info))
+}
+
+/// Decodes a PDF text string, which is either UTF-16BE or UTF-8 with a byte
+/// order mark, or PDFDocEncoding, close enough to Latin-1 for metadata.
+fn pdf_text(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(b"\xfe\xff") {
+        let units: Vec<u16> = utf16
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else if let Some(utf8) = bytes.strip_prefix(b"\xef\xbb\xbf") {
+        String::from_utf8_lossy(utf8).into_owned()
+    } else {
+        bytes.iter().map(|&b| char::from(b)).collect()
+    }
+}
+
+/// Reads the Dublin Core elements anywhere below `root`, which covers both
+/// the `<metadata>` of an OPF file and the `rdf:Description` of XMP, where
+/// values are wrapped in `rdf:Alt`, `rdf:Seq` or `rdf:Bag` lists.
+fn dublin_core(root: Node) -> Metadata {
+    let values = |namespace: &str, name: &str| -> Vec<String> {
+        root.descendants()
+            .filter(|node| node.has_tag_name((namespace, name)))
+            .flat_map(|node| {
+                let items: Vec<Node> = node
+                    .descendants()
+                    .filter(|item| item.has_tag_name("li"))
+                    .collect();
+                if items.is_empty() {
+                    vec![text(node)]
+                } else {
+                    items.into_iter().map(text).collect()
+                }
+            })
+            .filter_map(non_empty)
+            .collect()
+    };
+    let first = |name| values(DC, name).into_iter().next();
+    let creators = values(DC, "creator");
+    let mut identifiers = values(PRISM, "isbn");
+    identifiers.extend(values(DC, "identifier"));
+    Metadata {
+        title: first("title"),
+        author: (!creators.is_empty()).then(|| creators.join(", ")),
+        description: first("description")
+            .and_then(|description| non_empty(strip_tags(&description))),
+        language: first("language").and_then(non_empty),
+        issue_year: first("date").as_deref().and_then(parse_year),
+        isbn: identifiers.iter().find_map(|id| id.parse().ok()),
+        cover: None,
+    }
+}
+
+fn text(node: Node) -> String {
+    node.descendants()
+        .filter(Node::is_text)
+        .filter_map(|node| node.text())
+        .collect()
+}
+
+fn non_empty(s: String) -> Option<String> {
+    let s = s.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!s.is_empty()).then_some(s)
+}
+
+/// Descriptions are often escaped HTML.
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// The first four consecutive digits, as in `2001-05-03` or `D:20010503`.
+pub(crate) fn parse_year(date: &str) -> Option<i32> {
+    date.as_bytes()
+        .windows(4)
+        .find(|digits| digits.iter().all(u8::is_ascii_digit))
+        .and_then(|digits| std::str::from_utf8(digits).ok()?.parse().ok())
+}
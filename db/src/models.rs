@@ -1,70 +1,319 @@
-use crate::schema::{books, reviews};
+pub use crate::isbn::Isbn;
+use crate::schema::{book_files, books, reviews, works};
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
-use std::{str::FromStr, time::SystemTime};
+use std::{borrow::Cow, time::SystemTime};
 
-#[derive(Clone, Copy, Debug, diesel_derive_enum::DbEnum, Readable, Writable, PartialEq, Eq)]
-#[ExistingTypePath = "crate::schema::sql_types::Lang"]
-pub enum Lang {
-    English,
-    Russian,
-    Ukrainian,
-    German,
-    Chinese,
-    Japanese,
+/// A language books can be written in, from the `languages` table.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Readable, Writable, Serialize, Deserialize)]
+pub struct Language {
+    /// ISO 639-1, which books refer to.
+    pub code: String,
+    /// ISO 639-2/B, used by MARC.
+    pub bibliographic_code: String,
+    /// ISO 639-2/T.
+    pub terminology_code: String,
+    pub english_name: String,
+    pub native_name: String,
 }
 
-impl Lang {
-    pub fn to_str(self) -> &'static str {
-        match self {
-            Self::English => "English",
-            Self::Russian => "Russian",
-            Self::Ukrainian => "Ukrainian",
-            Self::German => "German",
-            Self::Chinese => "Chinese",
-            Self::Japanese => "Japanese",
-        }
+impl Language {
+    /// Finds the language with one of the codes, optionally with a region
+    /// like `en-US`, or one of the names, ignoring case.
+    pub fn find<'a>(languages: &'a [Self], s: &str) -> Option<&'a Self> {
+        let s = s.trim();
+        let primary = s.split(['-', '_']).next().unwrap_or_default();
+        languages.iter().find(|language| {
+            [
+                &language.code,
+                &language.bibliographic_code,
+                &language.terminology_code,
+            ]
+            .iter()
+            .any(|code| code.eq_ignore_ascii_case(primary))
+                || language.english_name.eq_ignore_ascii_case(s)
+                || language.native_name.to_lowercase() == s.to_lowercase()
+        })
     }
 }
 
-impl FromStr for Lang {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "English" => Ok(Self::English),
-            "Russian" => Ok(Self::Russian),
-            "Ukrainian" => Ok(Self::Ukrainian),
-            "German" => Ok(Self::German),
-            "Chinese" => Ok(Self::Chinese),
-            "Japanese" => Ok(Self::Japanese),
-            _ => Err(()),
-        }
-    }
-}
-
-#[derive(Debug, Queryable, Readable, Writable)]
+#[derive(Debug, Queryable, QueryableByName, Readable, Writable, Serialize, Deserialize)]
+#[diesel(table_name = books)]
 pub struct Book {
-    pub isbn: i64,
+    pub isbn: Isbn,
     pub title: String,
     pub author: String,
     pub description: String,
-    pub language: Lang,
     pub issue_year: i32,
+    pub archived_at: Option<SystemTime>,
+    /// [`Language::code`].
+    pub language: String,
+    /// [`Work::id`], if the catalog groups the book with other editions.
+    pub work_id: Option<i32>,
 }
 
-#[derive(Insertable)]
+#[derive(Insertable, Readable, Writable, Serialize, Deserialize)]
 #[diesel(table_name = books)]
 pub struct NewBook<'a> {
-    pub isbn: i64,
-    pub title: &'a str,
-    pub author: &'a str,
-    pub description: &'a str,
-    pub language: Lang,
+    pub isbn: Isbn,
+    #[serde(borrow)]
+    pub title: Cow<'a, str>,
+    #[serde(borrow)]
+    pub author: Cow<'a, str>,
+    #[serde(borrow)]
+    pub description: Cow<'a, str>,
+    /// [`Language::code`].
+    #[serde(borrow)]
+    pub language: Cow<'a, str>,
     pub issue_year: i32,
 }
 
-#[derive(Clone, Copy, Debug, diesel_derive_enum::DbEnum, Readable, Writable, PartialEq, Eq)]
+/// What to do with a book's reviews when the book is deleted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletePolicy {
+    /// Refuse to delete a book that has reviews.
+    #[default]
+    Restrict,
+    /// Delete the book together with its reviews.
+    Cascade,
+    /// Keep the book and its reviews but mark the book as archived.
+    Archive,
+}
+
+/// Partial update of a book; `None` fields are left unchanged.
+#[derive(Default, AsChangeset, Readable, Writable, Serialize, Deserialize)]
+#[diesel(table_name = books)]
+pub struct BookChanges<'a> {
+    #[serde(borrow)]
+    pub title: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    pub author: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    pub description: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    pub language: Option<Cow<'a, str>>,
+    pub issue_year: Option<i32>,
+}
+
+impl BookChanges<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.author.is_none()
+            && self.description.is_none()
+            && self.language.is_none()
+            && self.issue_year.is_none()
+    }
+}
+
+impl<'a> From<NewBook<'a>> for BookChanges<'a> {
+    fn from(book: NewBook<'a>) -> Self {
+        Self {
+            title: Some(book.title),
+            author: Some(book.author),
+            description: Some(book.description),
+            language: Some(book.language),
+            issue_year: Some(book.issue_year),
+        }
+    }
+}
+
+/// The text that books are editions or translations of.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Readable, Writable, Serialize, Deserialize)]
+pub struct Work {
+    pub id: i32,
+    /// Title in the original language.
+    pub title: String,
+    /// [`Language::code`] of the language the work was written in.
+    pub original_language: String,
+}
+
+#[derive(Insertable, AsChangeset, Readable, Writable, Serialize, Deserialize)]
+#[diesel(table_name = works)]
+pub struct NewWork<'a> {
+    #[serde(borrow)]
+    pub title: Cow<'a, str>,
+    /// [`Language::code`].
+    #[serde(borrow)]
+    pub original_language: Cow<'a, str>,
+}
+
+/// Books meant to be read in order, such as the volumes of a novel cycle.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Readable, Writable, Serialize, Deserialize)]
+pub struct Series {
+    pub id: i32,
+    pub title: String,
+}
+
+#[derive(Readable, Writable, Serialize, Deserialize)]
+pub struct NewSeries<'a> {
+    #[serde(borrow)]
+    pub title: Cow<'a, str>,
+}
+
+/// The place of a book in a series.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Readable, Writable, Serialize, Deserialize)]
+pub struct SeriesEntry {
+    pub series_id: i32,
+    /// [`Series::title`].
+    pub title: String,
+    pub volume: i32,
+}
+
+/// The place of a book in a series given by title, which is added to
+/// `series` if it is new.
+#[derive(Clone, Debug, Readable, Writable, Serialize, Deserialize)]
+pub struct NewSeriesEntry<'a> {
+    #[serde(borrow)]
+    pub title: Cow<'a, str>,
+    pub volume: i32,
+}
+
+/// A book of a series with its volume number.
+#[derive(Debug, Queryable, Readable, Writable, Serialize, Deserialize)]
+pub struct Volume {
+    pub volume: i32,
+    pub book: Book,
+}
+
+/// What a contributor did for a book.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    diesel_derive_enum::DbEnum,
+    Readable,
+    Writable,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    Hash,
+)]
+#[ExistingTypePath = "crate::schema::sql_types::ContributorRole"]
+#[serde(rename_all = "snake_case")]
+pub enum ContributorRole {
+    Author,
+    Translator,
+    Editor,
+    Illustrator,
+}
+
+impl ContributorRole {
+    pub const ALL: [Self; 4] = [
+        Self::Author,
+        Self::Translator,
+        Self::Editor,
+        Self::Illustrator,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Author => "author",
+            Self::Translator => "translator",
+            Self::Editor => "editor",
+            Self::Illustrator => "illustrator",
+        }
+    }
+}
+
+/// A person credited on books, in any role, by their preferred name.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Readable, Writable, Serialize, Deserialize)]
+pub struct Author {
+    pub id: i32,
+    pub name: String,
+}
+
+/// Writing system of a name.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    diesel_derive_enum::DbEnum,
+    Readable,
+    Writable,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+)]
+#[ExistingTypePath = "crate::schema::sql_types::Script"]
+#[serde(rename_all = "snake_case")]
+pub enum Script {
+    Latin,
+    Cyrillic,
+    /// Chinese characters, also used in Japanese and Korean names.
+    Han,
+    /// Japanese hiragana or katakana, possibly mixed with Han.
+    Kana,
+    Other,
+}
+
+impl Script {
+    /// Kana wins over Han, since Japanese names mix them, and Han over the
+    /// alphabets; otherwise the first letter decides.
+    pub fn detect(name: &str) -> Self {
+        let mut script = Self::Other;
+        for c in name.chars() {
+            match c {
+                '\u{3040}'..='\u{30ff}' => return Self::Kana,
+                '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' => script = Self::Han,
+                '\u{0400}'..='\u{04ff}' if script == Self::Other => script = Self::Cyrillic,
+                'a'..='z' | 'A'..='Z' | '\u{00c0}'..='\u{024f}' if script == Self::Other => {
+                    script = Self::Latin
+                }
+                _ => {}
+            }
+        }
+        script
+    }
+}
+
+/// Another name an author is known by, which also finds them.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Readable, Writable, Serialize, Deserialize)]
+pub struct AuthorName {
+    pub name: String,
+    pub script: Script,
+}
+
+#[derive(Clone, Debug, Readable, Writable, Serialize, Deserialize)]
+pub struct NewAuthorName<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    /// Detected from the name if `None`.
+    #[serde(default)]
+    pub script: Option<Script>,
+}
+
+/// One of the people credited on a book, in the order the book lists them.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Readable, Writable, Serialize, Deserialize)]
+pub struct Contributor {
+    pub author_id: i32,
+    pub name: String,
+    pub role: ContributorRole,
+}
+
+/// A contributor to record, found or added in `authors` by name.
+#[derive(Clone, Debug, Readable, Writable, Serialize, Deserialize)]
+pub struct NewContributor<'a> {
+    #[serde(borrow)]
+    pub name: Cow<'a, str>,
+    pub role: ContributorRole,
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    diesel_derive_enum::DbEnum,
+    Readable,
+    Writable,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+)]
 #[ExistingTypePath = "crate::schema::sql_types::Rating"]
 pub enum Rating {
     One,
@@ -74,9 +323,16 @@ pub enum Rating {
     Five,
 }
 
-#[derive(Debug, Queryable, Readable, Writable)]
+impl Rating {
+    /// From 1 to 5.
+    pub fn stars(self) -> u8 {
+        self as u8 + 1
+    }
+}
+
+#[derive(Debug, Queryable, Readable, Writable, Serialize, Deserialize)]
 pub struct Review {
-    pub isbn: i64,
+    pub isbn: Isbn,
     pub username: String,
     pub rating: Rating,
     pub description: String,
@@ -87,7 +343,7 @@ pub struct Review {
 #[derive(Insertable)]
 #[diesel(table_name = reviews)]
 pub struct NewReview<'a> {
-    pub isbn: i64,
+    pub isbn: Isbn,
     pub username: &'a str,
     pub rating: Rating,
     pub description: &'a str,
@@ -95,10 +351,71 @@ pub struct NewReview<'a> {
     pub updated_at: SystemTime,
 }
 
-#[derive(Readable, Writable)]
+#[derive(Readable, Writable, Serialize, Deserialize)]
 pub struct NewReviewPart<'a> {
-    pub isbn: i64,
-    pub username: &'a str,
+    pub isbn: Isbn,
+    #[serde(borrow)]
+    pub username: Cow<'a, str>,
     pub rating: Rating,
-    pub description: &'a str,
+    #[serde(borrow)]
+    pub description: Cow<'a, str>,
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    diesel_derive_enum::DbEnum,
+    Readable,
+    Writable,
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    Hash,
+)]
+#[ExistingTypePath = "crate::schema::sql_types::FileKind"]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    Cover,
+    Book,
+}
+
+impl FileKind {
+    pub const ALL: [Self; 2] = [Self::Cover, Self::Book];
+}
+
+/// A cover or book file, stored under its SHA-256 and possibly shared by
+/// several books.
+#[derive(Clone, Debug, Queryable, Insertable, Readable, Writable, Serialize, Deserialize)]
+#[diesel(table_name = book_files)]
+pub struct BookFile {
+    pub isbn: Isbn,
+    pub kind: FileKind,
+    /// Hex-encoded SHA-256 of the contents.
+    pub sha256: String,
+    pub size: i64,
+    pub mime: String,
+}
+
+/// Fixed sizes that cover thumbnails are generated in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl ThumbnailSize {
+    pub const ALL: [Self; 3] = [Self::Small, Self::Medium, Self::Large];
+
+    /// Largest width and height of the thumbnail, in pixels.
+    pub fn pixels(self) -> u32 {
+        match self {
+            Self::Small => 96,
+            Self::Medium => 192,
+            Self::Large => 384,
+        }
+    }
 }
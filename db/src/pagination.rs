@@ -0,0 +1,424 @@
+use crate::{
+    models::{Book, Isbn, Rating, Review},
+    schema::{books, reviews},
+    works, Error,
+};
+use diesel::{
+    pg::{Pg, PgConnection},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use speedy::{Readable, Writable};
+use std::{fmt::Write as _, str::FromStr, time::SystemTime};
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BookSortKey {
+    Title,
+    Author,
+    IssueYear,
+    Isbn,
+}
+
+impl FromStr for BookSortKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "title" => Ok(Self::Title),
+            "author" => Ok(Self::Author),
+            "issue_year" => Ok(Self::IssueYear),
+            "isbn" => Ok(Self::Isbn),
+            _ => Err(Error::InvalidInput(format!("unknown sort key `{s}`"))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReviewSortKey {
+    CreatedAt,
+    Rating,
+}
+
+impl FromStr for ReviewSortKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "created_at" => Ok(Self::CreatedAt),
+            "rating" => Ok(Self::Rating),
+            _ => Err(Error::InvalidInput(format!("unknown sort key `{s}`"))),
+        }
+    }
+}
+
+/// Sort key and direction, parsed from `key` or `-key` for descending order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sort<K> {
+    pub key: K,
+    pub descending: bool,
+}
+
+impl<K: FromStr<Err = Error>> FromStr for Sort<K> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('-') {
+            Some(key) => Ok(Self {
+                key: key.parse()?,
+                descending: true,
+            }),
+            None => Ok(Self {
+                key: s.parse()?,
+                descending: false,
+            }),
+        }
+    }
+}
+
+impl Default for Sort<BookSortKey> {
+    fn default() -> Self {
+        Self {
+            key: BookSortKey::Title,
+            descending: false,
+        }
+    }
+}
+
+impl Default for Sort<ReviewSortKey> {
+    fn default() -> Self {
+        Self {
+            key: ReviewSortKey::CreatedAt,
+            descending: true,
+        }
+    }
+}
+
+pub struct PageRequest<'a, K> {
+    pub sort: Sort<K>,
+    pub limit: i64,
+    /// Opaque token taken from [`Page::next_cursor`] of the previous page.
+    pub cursor: Option<&'a str>,
+}
+
+impl<K> Default for PageRequest<'_, K>
+where
+    Sort<K>: Default,
+{
+    fn default() -> Self {
+        Self {
+            sort: Default::default(),
+            limit: DEFAULT_LIMIT,
+            cursor: None,
+        }
+    }
+}
+
+impl<K> PageRequest<'_, K> {
+    fn limit(&self) -> i64 {
+        self.limit.clamp(1, MAX_LIMIT)
+    }
+}
+
+#[derive(Debug, Readable, Writable, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    fn new(mut items: Vec<T>, limit: i64, cursor: impl FnOnce(&T) -> String) -> Self {
+        let next_cursor = if items.len() as i64 > limit {
+            items.truncate(limit as usize);
+            items.last().map(cursor)
+        } else {
+            None
+        };
+        Self { items, next_cursor }
+    }
+}
+
+/// Position of the last row of a page: the sort key followed by the primary key.
+#[derive(Readable, Writable)]
+enum Cursor {
+    Title(String, i64),
+    Author(String, i64),
+    IssueYear(i32, i64),
+    Isbn(i64),
+    CreatedAt(SystemTime, i64, String),
+    Rating(Rating, i64, String),
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let bytes = self.write_to_vec().unwrap_or_default();
+        let mut s = String::with_capacity(bytes.len() * 2);
+        for b in bytes {
+            let _ = write!(s, "{b:02x}");
+        }
+        s
+    }
+
+    fn decode(s: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidInput("invalid cursor".into());
+        let bytes = s
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| match pair {
+                [hi, lo] => Some((hex_digit(*hi)? << 4) | hex_digit(*lo)?),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        Self::read_from_buffer_copying_data(&bytes).map_err(|_| invalid())
+    }
+
+    fn of_book(book: &Book, key: BookSortKey) -> Self {
+        match key {
+            BookSortKey::Title => Self::Title(book.title.clone(), book.isbn.into()),
+            BookSortKey::Author => Self::Author(book.author.clone(), book.isbn.into()),
+            BookSortKey::IssueYear => Self::IssueYear(book.issue_year, book.isbn.into()),
+            BookSortKey::Isbn => Self::Isbn(book.isbn.into()),
+        }
+    }
+
+    fn of_review(review: &Review, key: ReviewSortKey) -> Self {
+        match key {
+            ReviewSortKey::CreatedAt => Self::CreatedAt(
+                review.created_at,
+                review.isbn.into(),
+                review.username.clone(),
+            ),
+            ReviewSortKey::Rating => {
+                Self::Rating(review.rating, review.isbn.into(), review.username.clone())
+            }
+        }
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Keeps only the rows of `$query` strictly after `($key, $tie_key)`, or
+/// `($key, $tie_key, $tie2_key)`, in the order given by `order!` with the
+/// same columns.
+macro_rules! after {
+    ($query:expr, $descending:expr, $col:expr, $key:expr, $tie:expr, $tie_key:expr) => {
+        if $descending {
+            $query.filter(
+                $col.lt($key.clone())
+                    .or($col.eq($key).and($tie.lt($tie_key))),
+            )
+        } else {
+            $query.filter(
+                $col.gt($key.clone())
+                    .or($col.eq($key).and($tie.gt($tie_key))),
+            )
+        }
+    };
+    (
+        $query:expr, $descending:expr, $col:expr, $key:expr, $tie:expr, $tie_key:expr,
+        $tie2:expr, $tie2_key:expr
+    ) => {
+        if $descending {
+            $query.filter(
+                $col.lt($key.clone()).or($col.eq($key).and(
+                    $tie.lt($tie_key.clone())
+                        .or($tie.eq($tie_key).and($tie2.lt($tie2_key))),
+                )),
+            )
+        } else {
+            $query.filter(
+                $col.gt($key.clone()).or($col.eq($key).and(
+                    $tie.gt($tie_key.clone())
+                        .or($tie.eq($tie_key).and($tie2.gt($tie2_key))),
+                )),
+            )
+        }
+    };
+}
+
+macro_rules! order {
+    ($query:expr, $descending:expr, $col:expr, $tie:expr) => {
+        if $descending {
+            $query.order(($col.desc(), $tie.desc()))
+        } else {
+            $query.order(($col.asc(), $tie.asc()))
+        }
+    };
+    ($query:expr, $descending:expr, $col:expr, $tie:expr, $tie2:expr) => {
+        if $descending {
+            $query.order(($col.desc(), $tie.desc(), $tie2.desc()))
+        } else {
+            $query.order(($col.asc(), $tie.asc(), $tie2.asc()))
+        }
+    };
+}
+
+fn mismatched_cursor() -> Error {
+    Error::InvalidInput("cursor does not match the sort order".into())
+}
+
+pub(crate) fn books_page(
+    conn: &mut PgConnection,
+    mut query: books::BoxedQuery<'static, Pg>,
+    page: &PageRequest<BookSortKey>,
+) -> Result<Page<Book>, Error> {
+    let Sort { key, descending } = page.sort;
+    if let Some(cursor) = page.cursor {
+        query = match (key, Cursor::decode(cursor)?) {
+            (BookSortKey::Title, Cursor::Title(title, isbn)) => {
+                after!(query, descending, books::title, title, books::isbn, isbn)
+            }
+            (BookSortKey::Author, Cursor::Author(author, isbn)) => {
+                after!(query, descending, books::author, author, books::isbn, isbn)
+            }
+            (BookSortKey::IssueYear, Cursor::IssueYear(year, isbn)) => {
+                after!(
+                    query,
+                    descending,
+                    books::issue_year,
+                    year,
+                    books::isbn,
+                    isbn
+                )
+            }
+            (BookSortKey::Isbn, Cursor::Isbn(isbn)) if descending => {
+                query.filter(books::isbn.lt(isbn))
+            }
+            (BookSortKey::Isbn, Cursor::Isbn(isbn)) => query.filter(books::isbn.gt(isbn)),
+            _ => return Err(mismatched_cursor()),
+        };
+    }
+    query = match key {
+        BookSortKey::Title => order!(query, descending, books::title, books::isbn),
+        BookSortKey::Author => order!(query, descending, books::author, books::isbn),
+        BookSortKey::IssueYear => order!(query, descending, books::issue_year, books::isbn),
+        BookSortKey::Isbn if descending => query.order(books::isbn.desc()),
+        BookSortKey::Isbn => query.order(books::isbn.asc()),
+    };
+    let limit = page.limit();
+    let books = query.limit(limit + 1).load::<Book>(conn)?;
+    Ok(Page::new(books, limit, |book| {
+        Cursor::of_book(book, key).encode()
+    }))
+}
+
+/// Reviews of the book, or with `editions` of every edition of its work.
+pub(crate) fn reviews_by_book_page(
+    conn: &mut PgConnection,
+    isbn: Isbn,
+    editions: bool,
+    page: &PageRequest<ReviewSortKey>,
+) -> Result<Page<Review>, Error> {
+    let Sort { key, descending } = page.sort;
+    let isbns = if editions {
+        works::edition_isbns(conn, isbn)?
+    } else {
+        vec![isbn]
+    };
+    let mut query = reviews::table
+        .filter(reviews::isbn.eq_any(isbns))
+        .into_boxed::<Pg>();
+    // The username breaks ties, and the ISBN the remaining ones between
+    // editions reviewed by the same user.
+    if let Some(cursor) = page.cursor {
+        query = match (key, Cursor::decode(cursor)?) {
+            (ReviewSortKey::CreatedAt, Cursor::CreatedAt(at, isbn, username)) => {
+                after!(
+                    query,
+                    descending,
+                    reviews::created_at,
+                    at,
+                    reviews::username,
+                    username,
+                    reviews::isbn,
+                    isbn
+                )
+            }
+            (ReviewSortKey::Rating, Cursor::Rating(rating, isbn, username)) => {
+                after!(
+                    query,
+                    descending,
+                    reviews::rating,
+                    rating,
+                    reviews::username,
+                    username,
+                    reviews::isbn,
+                    isbn
+                )
+            }
+            _ => return Err(mismatched_cursor()),
+        };
+    }
+    query = match key {
+        ReviewSortKey::CreatedAt => order!(
+            query,
+            descending,
+            reviews::created_at,
+            reviews::username,
+            reviews::isbn
+        ),
+        ReviewSortKey::Rating => order!(
+            query,
+            descending,
+            reviews::rating,
+            reviews::username,
+            reviews::isbn
+        ),
+    };
+    let limit = page.limit();
+    let reviews = query.limit(limit + 1).load::<Review>(conn)?;
+    Ok(Page::new(reviews, limit, |review| {
+        Cursor::of_review(review, key).encode()
+    }))
+}
+
+pub(crate) fn reviews_by_username_page(
+    conn: &mut PgConnection,
+    username: &str,
+    page: &PageRequest<ReviewSortKey>,
+) -> Result<Page<Review>, Error> {
+    let Sort { key, descending } = page.sort;
+    let mut query = reviews::table
+        .filter(reviews::username.eq(username.to_owned()))
+        .into_boxed::<Pg>();
+    // The username is fixed, so the ISBN alone breaks ties.
+    if let Some(cursor) = page.cursor {
+        query = match (key, Cursor::decode(cursor)?) {
+            (ReviewSortKey::CreatedAt, Cursor::CreatedAt(at, isbn, _)) => {
+                after!(
+                    query,
+                    descending,
+                    reviews::created_at,
+                    at,
+                    reviews::isbn,
+                    isbn
+                )
+            }
+            (ReviewSortKey::Rating, Cursor::Rating(rating, isbn, _)) => {
+                after!(
+                    query,
+                    descending,
+                    reviews::rating,
+                    rating,
+                    reviews::isbn,
+                    isbn
+                )
+            }
+            _ => return Err(mismatched_cursor()),
+        };
+    }
+    query = match key {
+        ReviewSortKey::CreatedAt => order!(query, descending, reviews::created_at, reviews::isbn),
+        ReviewSortKey::Rating => order!(query, descending, reviews::rating, reviews::isbn),
+    };
+    let limit = page.limit();
+    let reviews = query.limit(limit + 1).load::<Review>(conn)?;
+    Ok(Page::new(reviews, limit, |review| {
+        Cursor::of_review(review, key).encode()
+    }))
+}
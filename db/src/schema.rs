@@ -1,26 +1,122 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
-    #[derive(diesel::sql_types::SqlType)]
-    #[diesel(postgres_type(name = "lang"))]
-    pub struct Lang;
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "contributor_role"))]
+    pub struct ContributorRole;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "file_kind"))]
+    pub struct FileKind;
 
     #[derive(diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "rating"))]
     pub struct Rating;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "regconfig", schema = "pg_catalog"))]
+    pub struct Regconfig;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "script"))]
+    pub struct Script;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
 }
 
 diesel::table! {
     use diesel::sql_types::*;
-    use super::sql_types::Lang;
+    use super::sql_types::Script;
 
+    author_names (name) {
+        name -> Text,
+        author_id -> Int4,
+        script -> Script,
+        search_key -> Text,
+    }
+}
+
+diesel::table! {
+    authors (id) {
+        id -> Int4,
+        name -> Text,
+        search_key -> Text,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::ContributorRole;
+
+    book_contributors (isbn, author_id, role) {
+        isbn -> Int8,
+        author_id -> Int4,
+        role -> ContributorRole,
+        position -> Int4,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::FileKind;
+
+    book_files (isbn, kind) {
+        isbn -> Int8,
+        kind -> FileKind,
+        sha256 -> Text,
+        size -> Int8,
+        mime -> Text,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
+    book_search (isbn) {
+        isbn -> Int8,
+        document -> Tsvector,
+    }
+}
+
+diesel::table! {
+    book_series (isbn, series_id) {
+        isbn -> Int8,
+        series_id -> Int4,
+        volume -> Int4,
+    }
+}
+
+diesel::table! {
     books (isbn) {
         isbn -> Int8,
         title -> Text,
         author -> Text,
         description -> Text,
-        language -> Lang,
         issue_year -> Int4,
+        archived_at -> Nullable<Timestamp>,
+        #[max_length = 3]
+        language -> Varchar,
+        work_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Regconfig;
+
+    languages (code) {
+        #[max_length = 3]
+        code -> Varchar,
+        #[max_length = 3]
+        bibliographic_code -> Bpchar,
+        #[max_length = 3]
+        terminology_code -> Bpchar,
+        english_name -> Text,
+        native_name -> Text,
+        search_config -> Regconfig,
     }
 }
 
@@ -30,6 +126,7 @@ diesel::table! {
 
     reviews (isbn, username) {
         isbn -> Int8,
+        #[max_length = 16]
         username -> Varchar,
         rating -> Rating,
         description -> Text,
@@ -38,9 +135,43 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    series (id) {
+        id -> Int4,
+        title -> Text,
+    }
+}
+
+diesel::table! {
+    works (id) {
+        id -> Int4,
+        title -> Text,
+        #[max_length = 3]
+        original_language -> Varchar,
+    }
+}
+
+diesel::joinable!(author_names -> authors (author_id));
+diesel::joinable!(book_contributors -> authors (author_id));
+diesel::joinable!(book_contributors -> books (isbn));
+diesel::joinable!(book_files -> books (isbn));
+diesel::joinable!(book_search -> books (isbn));
+diesel::joinable!(book_series -> books (isbn));
+diesel::joinable!(book_series -> series (series_id));
+diesel::joinable!(books -> languages (language));
+diesel::joinable!(books -> works (work_id));
 diesel::joinable!(reviews -> books (isbn));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    author_names,
+    authors,
+    book_contributors,
+    book_files,
+    book_search,
+    book_series,
     books,
+    languages,
     reviews,
+    series,
+    works,
 );
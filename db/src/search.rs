@@ -0,0 +1,70 @@
+use crate::{
+    filter::BookFilter,
+    models::Book,
+    schema::{book_search, books},
+    Error,
+};
+use diesel::{
+    dsl::{sql, AsExprOf},
+    expression::{SqlLiteral, TypedExpressionType, UncheckedBind},
+    pg::PgConnection,
+    prelude::*,
+    sql_types::{Bool, Float4, Text},
+};
+use serde::{Deserialize, Serialize};
+use speedy::{Readable, Writable};
+
+pub const MAX_RESULTS: i64 = 100;
+
+#[derive(Debug, Readable, Writable, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub book: Book,
+    pub rank: f32,
+}
+
+// The query is parsed with the search configuration of every language, since
+// the language of the query itself is unknown, and the results are OR-ed.
+const PARSE_QUERY: (&str, &str) = (
+    "(SELECT tsquery_or_agg(websearch_to_tsquery(config, ",
+    ")) FROM (SELECT search_config FROM languages UNION SELECT 'simple') AS configs(config))",
+);
+
+type WithQuery<ST> = SqlLiteral<ST, UncheckedBind<SqlLiteral<ST>, AsExprOf<String, Text>>>;
+
+/// SQL with the parsed query between `before` and `after`.
+fn with_query<ST: TypedExpressionType>(before: &str, query: &str, after: &str) -> WithQuery<ST> {
+    sql::<ST>(&format!("{before}{}", PARSE_QUERY.0))
+        .bind::<Text, _>(query.to_owned())
+        .sql(&format!("{}{after}", PARSE_QUERY.1))
+}
+
+pub(crate) fn search_books(
+    conn: &mut PgConnection,
+    query: &str,
+    filter: &BookFilter,
+    limit: i64,
+) -> Result<Vec<SearchHit>, Error> {
+    if query.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let rank = || {
+        with_query::<Float4>(
+            "ts_rank((SELECT document FROM book_search WHERE book_search.isbn = books.isbn), ",
+            query,
+            ")",
+        )
+    };
+    let matching = book_search::table
+        .select(book_search::isbn)
+        .filter(with_query::<Bool>("book_search.document @@ ", query, ""));
+    Ok(filter
+        .apply(books::table.into_boxed())
+        .filter(books::isbn.eq_any(matching))
+        .select((books::all_columns, rank()))
+        .order((rank().desc(), books::isbn))
+        .limit(limit.clamp(1, MAX_RESULTS))
+        .load::<(Book, f32)>(conn)?
+        .into_iter()
+        .map(|(book, rank)| SearchHit { book, rank })
+        .collect())
+}
@@ -0,0 +1,101 @@
+//! Series of books and the volume number of each book in them.
+//!
+//! A book can belong to several series, such as a cycle and a sub-cycle of
+//! it. Editions of the same volume, like its translations, share the volume
+//! number.
+
+use crate::{
+    models::{Isbn, NewSeries, NewSeriesEntry, Series, SeriesEntry, Volume},
+    schema::{book_series, books, series},
+    validation, Error,
+};
+use diesel::{pg::PgConnection, prelude::*, upsert::excluded};
+
+/// Fails with [`Error::UniqueViolation`] if a series has the same title.
+pub(crate) fn create(conn: &mut PgConnection, new: &NewSeries) -> Result<Series, Error> {
+    validation::ensure_valid(validation::check_series_title(&new.title))?;
+    Ok(diesel::insert_into(series::table)
+        .values(series::title.eq(new.title.trim()))
+        .get_result(conn)?)
+}
+
+pub(crate) fn get(conn: &mut PgConnection, id: i32) -> Result<Series, Error> {
+    Ok(series::table.find(id).first(conn)?)
+}
+
+/// Every series, by title.
+pub(crate) fn load(conn: &mut PgConnection) -> Result<Vec<Series>, Error> {
+    Ok(series::table.order(series::title).load(conn)?)
+}
+
+pub(crate) fn rename(conn: &mut PgConnection, id: i32, new: &NewSeries) -> Result<Series, Error> {
+    validation::ensure_valid(validation::check_series_title(&new.title))?;
+    Ok(diesel::update(series::table.find(id))
+        .set(series::title.eq(new.title.trim()))
+        .get_result(conn)?)
+}
+
+/// The books stay in the catalog.
+pub(crate) fn delete(conn: &mut PgConnection, id: i32) -> Result<usize, Error> {
+    Ok(diesel::delete(series::table.find(id)).execute(conn)?)
+}
+
+/// Books of the series in circulation in reading order. Editions of the same
+/// volume are listed oldest first.
+pub(crate) fn volumes(conn: &mut PgConnection, id: i32) -> Result<Vec<Volume>, Error> {
+    Ok(book_series::table
+        .inner_join(books::table)
+        .filter(book_series::series_id.eq(id))
+        .filter(books::archived_at.is_null())
+        .order((book_series::volume, books::issue_year, books::isbn))
+        .select((book_series::volume, books::all_columns))
+        .load(conn)?)
+}
+
+/// The series a book belongs to, by title.
+pub(crate) fn get_for_book(conn: &mut PgConnection, isbn: Isbn) -> Result<Vec<SeriesEntry>, Error> {
+    Ok(book_series::table
+        .inner_join(series::table)
+        .filter(book_series::isbn.eq(isbn))
+        .order(series::title)
+        .select((series::id, series::title, book_series::volume))
+        .load(conn)?)
+}
+
+/// Replaces the series a book belongs to, adding the ones with a new title.
+///
+/// Fails with [`Error::Validation`] if [`validation::check_series_entries`]
+/// finds problems.
+pub(crate) fn set_for_book(
+    conn: &mut PgConnection,
+    isbn: Isbn,
+    entries: &[NewSeriesEntry],
+) -> Result<Vec<SeriesEntry>, Error> {
+    validation::ensure_valid(validation::check_series_entries(entries))?;
+    conn.transaction(|conn| {
+        books::table
+            .find(isbn)
+            .select(books::isbn)
+            .for_update()
+            .first::<Isbn>(conn)?;
+        diesel::delete(book_series::table.filter(book_series::isbn.eq(isbn))).execute(conn)?;
+        for entry in entries {
+            let series_id = diesel::insert_into(series::table)
+                .values(series::title.eq(entry.title.trim()))
+                .on_conflict(series::title)
+                // a no-op update, so that the existing id is returned
+                .do_update()
+                .set(series::title.eq(excluded(series::title)))
+                .returning(series::id)
+                .get_result::<i32>(conn)?;
+            diesel::insert_into(book_series::table)
+                .values((
+                    book_series::isbn.eq(isbn),
+                    book_series::series_id.eq(series_id),
+                    book_series::volume.eq(entry.volume),
+                ))
+                .execute(conn)?;
+        }
+        get_for_book(conn, isbn)
+    })
+}
@@ -0,0 +1,593 @@
+//! Cover and book files kept next to the database rows they belong to.
+//!
+//! Files are content-addressed: each distinct file is stored once under
+//! `objects/{sha256}` and the `book_files` table maps books to it. Changes
+//! are staged first and only made visible from inside the database
+//! transaction, right before it commits, so that a failed insert leaves no
+//! stray files and a failed copy leaves no rows without files.
+//!
+//! Covers get JPEG thumbnails in every [`ThumbnailSize`], stored as
+//! `thumbnails/{sha256}-{pixels}` and deleted together with their cover.
+
+use crate::{
+    blob::BlobStore,
+    models::{BookFile, DeletePolicy, FileKind, Isbn, ThumbnailSize},
+    schema::{book_files, books},
+    Error,
+};
+use diesel::{dsl::exists, pg::PgConnection, prelude::*, sql_types::Text};
+use image::{codecs::jpeg::JpegEncoder, DynamicImage};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const OBJECTS_DIR: &str = "objects";
+const STAGING_DIR: &str = ".staging";
+const ORPHANED_DIR: &str = "orphaned";
+const THUMBNAILS_DIR: &str = "thumbnails";
+const THUMBNAIL_QUALITY: u8 = 85;
+/// Directories of the layout keyed by ISBN, used before files were
+/// content-addressed.
+const LEGACY_DIRS: [(FileKind, &str); 2] = [(FileKind::Cover, "covers"), (FileKind::Book, "books")];
+
+fn object_key(sha256: &str) -> String {
+    format!("{OBJECTS_DIR}/{sha256}")
+}
+
+fn thumbnail_key(sha256: &str, size: ThumbnailSize) -> String {
+    format!("{THUMBNAILS_DIR}/{sha256}-{}", size.pixels())
+}
+
+fn key_name(key: &str) -> &str {
+    key.rsplit('/').next().unwrap_or(key)
+}
+
+/// Serializes the transactions that may create or delete the object with the
+/// given hash, until the current transaction ends.
+fn lock_object(conn: &mut PgConnection, sha256: &str) -> Result<(), Error> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind::<Text, _>(sha256)
+        .execute(conn)?;
+    Ok(())
+}
+
+pub struct Storage {
+    blobs: Box<dyn BlobStore>,
+}
+
+impl Storage {
+    pub fn new(blobs: Box<dyn BlobStore>) -> Self {
+        Self { blobs }
+    }
+
+    pub fn file(
+        &self,
+        conn: &mut PgConnection,
+        kind: FileKind,
+        isbn: Isbn,
+    ) -> Result<BookFile, Error> {
+        Ok(book_files::table.find((isbn, kind)).first(conn)?)
+    }
+
+    pub fn open(&self, file: &BookFile) -> Result<Box<dyn Read + Send>, Error> {
+        Ok(self.blobs.get(&object_key(&file.sha256))?)
+    }
+
+    /// Reads at most `len` bytes of the file, starting at offset `start`.
+    pub fn open_range(
+        &self,
+        file: &BookFile,
+        start: u64,
+        len: u64,
+    ) -> Result<Box<dyn Read + Send>, Error> {
+        Ok(self
+            .blobs
+            .get_range(&object_key(&file.sha256), start, len)?)
+    }
+
+    /// The cover scaled down to fit `size`, as a JPEG. Thumbnails missing
+    /// from the store, such as those of covers added before thumbnails
+    /// existed, are generated and cached.
+    pub fn thumbnail(&self, file: &BookFile, size: ThumbnailSize) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        match self.blobs.get(&thumbnail_key(&file.sha256, size)) {
+            Ok(mut blob) => {
+                blob.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // uploads were decoded when their thumbnails were made, so
+                // the stored cover is at fault and not the request
+                let cover =
+                    image::load_from_memory(&self.read_cover(&file.sha256)?).map_err(|e| {
+                        Error::Io(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("stored cover {} is not a readable image: {e}", file.sha256),
+                        ))
+                    })?;
+                let bytes = encode_thumbnail(&cover, size)?;
+                self.blobs
+                    .put(&thumbnail_key(&file.sha256, size), &mut bytes.as_slice())?;
+                Ok(bytes)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn read_cover(&self, sha256: &str) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        self.blobs
+            .get(&object_key(sha256))?
+            .read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Generates the thumbnails of a newly stored cover. Fails with
+    /// [`Error::InvalidInput`] if the upload cannot be decoded after all.
+    fn create_thumbnails(&self, sha256: &str) -> Result<(), Error> {
+        let cover = image::load_from_memory(&self.read_cover(sha256)?)
+            .map_err(|e| Error::InvalidInput(format!("cover is not a readable image: {e}")))?;
+        for size in ThumbnailSize::ALL {
+            let bytes = encode_thumbnail(&cover, size)?;
+            self.blobs
+                .put(&thumbnail_key(sha256, size), &mut bytes.as_slice())?;
+        }
+        Ok(())
+    }
+
+    /// Deletes or archives a book like [`crate::delete_book`], removing its
+    /// files unless it is only archived. Fails with [`Error::NotFound`] if
+    /// nothing was deleted.
+    pub fn delete_book(
+        &self,
+        conn: &mut PgConnection,
+        isbn: Isbn,
+        policy: DeletePolicy,
+    ) -> Result<(), Error> {
+        let mut files = self.transaction();
+        if policy != DeletePolicy::Archive {
+            for kind in FileKind::ALL {
+                files.stage_removal(kind, isbn);
+            }
+        }
+        // a missing book must not take its files with it
+        files.run(conn, |conn| match crate::delete_book(conn, isbn, policy)? {
+            0 => Err(Error::NotFound),
+            _ => Ok(()),
+        })
+    }
+
+    pub fn transaction(&self) -> FileTransaction<'_> {
+        FileTransaction {
+            storage: self,
+            steps: Vec::new(),
+        }
+    }
+
+    /// A fresh key in the staging directory, unique across processes and
+    /// machines sharing the store.
+    fn temp_key(&self, name: &str) -> String {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        format!("{STAGING_DIR}/{name}-{nanos}-{}-{n}", std::process::id())
+    }
+
+    /// Deletes the object unless some book still refers to it.
+    fn collect(&self, conn: &mut PgConnection, sha256: &str) -> Result<(), Error> {
+        conn.transaction(|conn| {
+            lock_object(conn, sha256)?;
+            let refs: i64 = book_files::table
+                .filter(book_files::sha256.eq(sha256))
+                .count()
+                .get_result(conn)?;
+            if refs == 0 {
+                self.blobs.delete(&object_key(sha256))?;
+                for size in ThumbnailSize::ALL {
+                    self.blobs.delete(&thumbnail_key(sha256, size))?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Compares the stored files with the `books` and `book_files` tables.
+    pub fn check(&self, conn: &mut PgConnection) -> Result<ConsistencyReport, Error> {
+        let files = book_files::table
+            .order((book_files::isbn, book_files::kind))
+            .load::<BookFile>(conn)?;
+        let objects: HashSet<String> = self
+            .blobs
+            .list(OBJECTS_DIR)?
+            .iter()
+            .map(|key| key_name(key).to_owned())
+            .collect();
+        let referenced: HashSet<&str> = files.iter().map(|file| file.sha256.as_str()).collect();
+
+        let mut report = ConsistencyReport::default();
+        report.orphaned_files.extend(
+            objects
+                .iter()
+                .filter(|sha256| !referenced.contains(sha256.as_str()))
+                .map(|sha256| object_key(sha256)),
+        );
+        report
+            .orphaned_files
+            .extend(self.blobs.list(THUMBNAILS_DIR)?.into_iter().filter(|key| {
+                let sha256 = key_name(key).split('-').next().unwrap_or_default();
+                !referenced.contains(sha256)
+            }));
+        // leftovers of a process that died in the middle of a transaction
+        report.orphaned_files.extend(self.blobs.list(STAGING_DIR)?);
+        // books without files are fine, rows without their object are not
+        report.missing_files.extend(
+            files
+                .iter()
+                .filter(|file| !objects.contains(&file.sha256))
+                .cloned(),
+        );
+        for (_, dir) in LEGACY_DIRS {
+            report.legacy_files.extend(self.blobs.list(dir)?);
+        }
+        Ok(report)
+    }
+
+    /// Forgets the files whose object is missing, adopts files of the old
+    /// layout that belong to a book and moves other unused files into
+    /// `orphaned/`. Books are left as they are, with fewer files.
+    pub fn repair(&self, conn: &mut PgConnection, report: &ConsistencyReport) -> Result<(), Error> {
+        for file in &report.missing_files {
+            // a file uploaded since the check has a different hash
+            diesel::delete(
+                book_files::table
+                    .find((file.isbn, file.kind))
+                    .filter(book_files::sha256.eq(&file.sha256)),
+            )
+            .execute(conn)?;
+        }
+        for key in &report.legacy_files {
+            let (dir, name) = key.rsplit_once('/').unwrap_or(("", key));
+            let kind = LEGACY_DIRS
+                .iter()
+                .find_map(|&(kind, legacy)| (legacy == dir).then_some(kind));
+            let target = match (kind, Isbn::parse_stored(name)) {
+                (Some(kind), Ok(isbn)) if self.adoptable(conn, isbn, kind)? => Some((isbn, kind)),
+                _ => None,
+            };
+            if let Some((isbn, kind)) = target {
+                let mut files = self.transaction();
+                files.stage(kind, isbn, &mut self.blobs.get(key)?)?;
+                files.run(conn, |_| Ok(()))?;
+                self.blobs.delete(key)?;
+            } else {
+                self.blobs
+                    .rename(key, &format!("{ORPHANED_DIR}/{}", key.replace('/', "-")))?;
+            }
+        }
+        for key in &report.orphaned_files {
+            self.blobs
+                .rename(key, &format!("{ORPHANED_DIR}/{}", key_name(key)))?;
+        }
+        Ok(())
+    }
+
+    /// Whether the book exists and has no file of the given kind.
+    fn adoptable(
+        &self,
+        conn: &mut PgConnection,
+        isbn: Isbn,
+        kind: FileKind,
+    ) -> Result<bool, Error> {
+        let book = diesel::select(exists(books::table.find(isbn))).get_result::<bool>(conn)?;
+        let file = diesel::select(exists(book_files::table.find((isbn, kind))))
+            .get_result::<bool>(conn)?;
+        Ok(book && !file)
+    }
+
+    /// Rehashes every stored object and reports those whose contents no
+    /// longer match the `book_files` table.
+    pub fn verify(&self, conn: &mut PgConnection) -> Result<Vec<Mismatch>, Error> {
+        let mut objects: HashMap<String, Vec<BookFile>> = HashMap::new();
+        for file in book_files::table
+            .order((book_files::isbn, book_files::kind))
+            .load::<BookFile>(conn)?
+        {
+            objects.entry(file.sha256.clone()).or_default().push(file);
+        }
+        let mut mismatches = Vec::new();
+        for (sha256, files) in objects {
+            let actual = match self.blobs.get(&object_key(&sha256)) {
+                Ok(mut blob) => {
+                    let mut reader = HashingReader::new(&mut *blob);
+                    io::copy(&mut reader, &mut io::sink())?;
+                    Some(reader.finish())
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+            let matches = actual.as_ref().is_some_and(|(actual_sha256, size)| {
+                *actual_sha256 == sha256 && files.iter().all(|file| file.size == *size)
+            });
+            if !matches {
+                mismatches.push(Mismatch {
+                    sha256,
+                    files,
+                    actual,
+                });
+            }
+        }
+        mismatches.sort_by(|a, b| a.sha256.cmp(&b.sha256));
+        Ok(mismatches)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConsistencyReport {
+    /// Keys of files that belong to no book.
+    pub orphaned_files: Vec<String>,
+    /// Files of books whose object is missing from the store. Books without
+    /// a cover or book file are not listed, since both are optional.
+    pub missing_files: Vec<BookFile>,
+    /// Keys of files stored by ISBN, which can be adopted by their books.
+    pub legacy_files: Vec<String>,
+}
+
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.orphaned_files.is_empty()
+            && self.missing_files.is_empty()
+            && self.legacy_files.is_empty()
+    }
+}
+
+/// A stored object that does not hash to its key or has the wrong size.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub sha256: String,
+    /// Rows of `book_files` referring to the object.
+    pub files: Vec<BookFile>,
+    /// Hash and size of what is actually stored, `None` if the object is missing.
+    pub actual: Option<(String, i64)>,
+}
+
+/// Hashes and measures everything read through it, and keeps the first bytes
+/// for guessing the MIME type.
+struct HashingReader<'a> {
+    inner: &'a mut dyn Read,
+    hasher: Sha256,
+    size: i64,
+    head: Vec<u8>,
+}
+
+impl<'a> HashingReader<'a> {
+    const HEAD_LEN: usize = 64;
+
+    fn new(inner: &'a mut dyn Read) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+            head: Vec::with_capacity(Self::HEAD_LEN),
+        }
+    }
+
+    /// Hex-encoded hash and size of the data read so far.
+    fn finish(self) -> (String, i64) {
+        let mut sha256 = String::with_capacity(64);
+        for b in self.hasher.finalize() {
+            let _ = write!(sha256, "{b:02x}");
+        }
+        (sha256, self.size)
+    }
+}
+
+impl Read for HashingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as i64;
+        let missing = Self::HEAD_LEN - self.head.len();
+        self.head.extend_from_slice(&buf[..n.min(missing)]);
+        Ok(n)
+    }
+}
+
+/// Scales `cover` down to fit `size`; smaller covers are only re-encoded.
+fn encode_thumbnail(cover: &DynamicImage, size: ThumbnailSize) -> Result<Vec<u8>, Error> {
+    let pixels = size.pixels();
+    let rgb = if cover.width() > pixels || cover.height() > pixels {
+        cover.thumbnail(pixels, pixels).to_rgb8()
+    } else {
+        cover.to_rgb8()
+    };
+    let mut bytes = Vec::new();
+    JpegEncoder::new_with_quality(&mut bytes, THUMBNAIL_QUALITY)
+        .encode_image(&rgb)
+        .map_err(|e| Error::Io(io::Error::other(e)))?;
+    Ok(bytes)
+}
+
+/// Guesses the MIME type of a file from its first bytes.
+pub fn sniff_mime(head: &[u8]) -> &'static str {
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if head.starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        "image/gif"
+    } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+        "image/webp"
+    } else if head.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else if head.starts_with(b"PK\x03\x04") {
+        // an EPUB starts with an uncompressed `mimetype` entry
+        if head
+            .get(30..)
+            .is_some_and(|rest| rest.starts_with(b"mimetypeapplication/epub+zip"))
+        {
+            "application/epub+zip"
+        } else {
+            "application/zip"
+        }
+    } else {
+        "application/octet-stream"
+    }
+}
+
+enum Step {
+    /// A copy of the new file waiting in the staging directory.
+    Put {
+        staged: String,
+        file: BookFile,
+    },
+    Remove {
+        kind: FileKind,
+        isbn: Isbn,
+    },
+}
+
+/// File changes that are applied together with a database transaction.
+pub struct FileTransaction<'a> {
+    storage: &'a Storage,
+    steps: Vec<Step>,
+}
+
+impl FileTransaction<'_> {
+    /// Copies `source` into the staging area, to replace the file of the given
+    /// kind on commit.
+    pub fn stage_copy(&mut self, kind: FileKind, isbn: Isbn, source: &Path) -> Result<(), Error> {
+        self.stage(kind, isbn, &mut File::open(source)?)
+    }
+
+    /// Like [`Self::stage_copy`], with the contents read from `data`.
+    pub fn stage(&mut self, kind: FileKind, isbn: Isbn, data: &mut dyn Read) -> Result<(), Error> {
+        let blobs = &self.storage.blobs;
+        let staged = self.storage.temp_key(&format!("{isbn}-{kind:?}"));
+        let mut reader = HashingReader::new(data);
+        if let Err(e) = blobs.put(&staged, &mut reader) {
+            let _ = blobs.delete(&staged);
+            return Err(e.into());
+        }
+        let mime = sniff_mime(&reader.head).to_owned();
+        let (sha256, size) = reader.finish();
+        self.steps.push(Step::Put {
+            staged,
+            file: BookFile {
+                isbn,
+                kind,
+                sha256,
+                size,
+                mime,
+            },
+        });
+        Ok(())
+    }
+
+    /// Removes the file of the given kind on commit, if it exists.
+    pub fn stage_removal(&mut self, kind: FileKind, isbn: Isbn) {
+        self.steps.push(Step::Remove { kind, isbn });
+    }
+
+    /// Runs `f` in a database transaction together with the staged changes.
+    /// If either `f`, the file changes or the commit fail, the files are left
+    /// as they were.
+    ///
+    /// Removals happen before `f` and new files are added after it, so `f`
+    /// may delete or create the book itself.
+    pub fn run<T>(
+        self,
+        conn: &mut PgConnection,
+        f: impl FnOnce(&mut PgConnection) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        // objects that were created, and objects that may have lost their
+        // last reference, in this transaction
+        let mut created = Vec::new();
+        let mut released = Vec::new();
+        let result = conn.transaction(|conn| {
+            self.remove(conn, &mut released)?;
+            let value = f(conn)?;
+            self.put(conn, &mut created, &mut released)?;
+            Ok(value)
+        });
+        let unused = if result.is_ok() { released } else { created };
+        for sha256 in unused {
+            // a leftover is harmless and will be reported by `check`
+            let _ = self.storage.collect(conn, &sha256);
+        }
+        result
+    }
+
+    fn remove(&self, conn: &mut PgConnection, released: &mut Vec<String>) -> Result<(), Error> {
+        for step in &self.steps {
+            if let Step::Remove { kind, isbn } = step {
+                let sha256 = diesel::delete(book_files::table.find((isbn, kind)))
+                    .returning(book_files::sha256)
+                    .get_result::<String>(conn)
+                    .optional()?;
+                released.extend(sha256);
+            }
+        }
+        Ok(())
+    }
+
+    fn put(
+        &self,
+        conn: &mut PgConnection,
+        created: &mut Vec<String>,
+        released: &mut Vec<String>,
+    ) -> Result<(), Error> {
+        let blobs = &self.storage.blobs;
+        for step in &self.steps {
+            if let Step::Put { staged, file } = step {
+                lock_object(conn, &file.sha256)?;
+                let key = object_key(&file.sha256);
+                // identical contents are stored once
+                if !blobs.exists(&key)? {
+                    blobs.rename(staged, &key)?;
+                    created.push(file.sha256.clone());
+                    if file.kind == FileKind::Cover {
+                        self.storage.create_thumbnails(&file.sha256)?;
+                    }
+                }
+                let previous = book_files::table
+                    .find((file.isbn, file.kind))
+                    .select(book_files::sha256)
+                    .for_update()
+                    .first::<String>(conn)
+                    .optional()?;
+                diesel::insert_into(book_files::table)
+                    .values(file)
+                    .on_conflict((book_files::isbn, book_files::kind))
+                    .do_update()
+                    .set((
+                        book_files::sha256.eq(&file.sha256),
+                        book_files::size.eq(file.size),
+                        book_files::mime.eq(&file.mime),
+                    ))
+                    .execute(conn)?;
+                released.extend(previous.filter(|previous| *previous != file.sha256));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileTransaction<'_> {
+    fn drop(&mut self) {
+        for step in &self.steps {
+            if let Step::Put { staged, .. } = step {
+                // already gone if it was moved into place
+                let _ = self.storage.blobs.delete(staged);
+            }
+        }
+    }
+}
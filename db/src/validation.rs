@@ -0,0 +1,334 @@
+//! Checks of books and reviews before they are written, so that bad input is
+//! reported field by field instead of failing in the database.
+
+use crate::{
+    contributors,
+    models::{BookChanges, Isbn, NewBook, NewContributor, NewReview, NewSeriesEntry, NewWork},
+    schema::{books, languages},
+    Error,
+};
+use diesel::{dsl::exists, pg::PgConnection, prelude::*};
+use serde::{Deserialize, Serialize};
+use speedy::{Readable, Writable};
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const MAX_TITLE_LEN: usize = 512;
+pub const MAX_AUTHOR_LEN: usize = 512;
+pub const MAX_DESCRIPTION_LEN: usize = 20_000;
+/// Length of the `varchar` column.
+pub const MAX_USERNAME_LEN: usize = 16;
+/// Nothing older than the printing press is expected in the catalog.
+pub const MIN_ISSUE_YEAR: i32 = 1450;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Readable, Writable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Isbn,
+    Title,
+    Author,
+    Description,
+    Language,
+    IssueYear,
+    Username,
+    Rating,
+    Contributors,
+    Name,
+    OriginalLanguage,
+    Series,
+    Volume,
+}
+
+impl Field {
+    /// The name used in the models and in requests.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Isbn => "isbn",
+            Self::Title => "title",
+            Self::Author => "author",
+            Self::Description => "description",
+            Self::Language => "language",
+            Self::IssueYear => "issue_year",
+            Self::Username => "username",
+            Self::Rating => "rating",
+            Self::Contributors => "contributors",
+            Self::Name => "name",
+            Self::OriginalLanguage => "original_language",
+            Self::Series => "series",
+            Self::Volume => "volume",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Readable, Writable, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: Field,
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.field.as_str(), self.message)
+    }
+}
+
+/// Latest accepted issue year: books are often announced a year ahead.
+pub fn max_issue_year() -> i32 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    // average Gregorian year, off by at most a day around new year
+    1970 + (secs / 31_556_952) as i32 + 1
+}
+
+pub fn check_book(book: &NewBook) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    isbn(&mut errors, book.isbn);
+    text(&mut errors, Field::Title, &book.title, MAX_TITLE_LEN, true);
+    text(
+        &mut errors,
+        Field::Author,
+        &book.author,
+        MAX_AUTHOR_LEN,
+        true,
+    );
+    text(
+        &mut errors,
+        Field::Description,
+        &book.description,
+        MAX_DESCRIPTION_LEN,
+        false,
+    );
+    issue_year(&mut errors, book.issue_year);
+    errors
+}
+
+/// Checks only the fields that are changed.
+pub fn check_book_changes(changes: &BookChanges) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    if let Some(title) = &changes.title {
+        text(&mut errors, Field::Title, title, MAX_TITLE_LEN, true);
+    }
+    if let Some(author) = &changes.author {
+        text(&mut errors, Field::Author, author, MAX_AUTHOR_LEN, true);
+    }
+    if let Some(description) = &changes.description {
+        text(
+            &mut errors,
+            Field::Description,
+            description,
+            MAX_DESCRIPTION_LEN,
+            false,
+        );
+    }
+    if let Some(year) = changes.issue_year {
+        issue_year(&mut errors, year);
+    }
+    errors
+}
+
+/// A book needs at least one author, and its byline must fit in
+/// [`MAX_AUTHOR_LEN`].
+pub fn check_contributors(contributors: &[NewContributor]) -> Vec<FieldError> {
+    let message = contributors
+        .iter()
+        .enumerate()
+        .find_map(|(i, contributor)| {
+            let name = contributor.name.trim();
+            if name.is_empty() {
+                Some("names must not be empty".into())
+            } else if name.chars().count() > MAX_AUTHOR_LEN {
+                Some(format!(
+                    "names must be at most {MAX_AUTHOR_LEN} characters long"
+                ))
+            } else if contributors[..i]
+                .iter()
+                .any(|other| other.role == contributor.role && other.name.trim() == name)
+            {
+                Some(format!(
+                    "`{name}` is listed twice as {}",
+                    contributor.role.as_str()
+                ))
+            } else {
+                None
+            }
+        })
+        .or_else(|| {
+            let byline = contributors::byline(contributors);
+            if byline.is_empty() {
+                Some("must include an author".into())
+            } else if byline.chars().count() > MAX_AUTHOR_LEN {
+                Some(format!(
+                    "authors must be at most {MAX_AUTHOR_LEN} characters long together"
+                ))
+            } else {
+                None
+            }
+        });
+    message
+        .map(|message| FieldError {
+            field: Field::Contributors,
+            message,
+        })
+        .into_iter()
+        .collect()
+}
+
+/// The title of a series.
+pub fn check_series_title(title: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    text(&mut errors, Field::Series, title, MAX_TITLE_LEN, true);
+    errors
+}
+
+/// Volumes start at 1, and a book is in a series at most once.
+pub fn check_series_entries(entries: &[NewSeriesEntry]) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let title = entry.title.trim();
+        errors.extend(check_series_title(title));
+        if entries[..i].iter().any(|other| other.title.trim() == title) {
+            errors.push(FieldError {
+                field: Field::Series,
+                message: format!("`{title}` is listed twice"),
+            });
+        }
+        if entry.volume < 1 {
+            errors.push(FieldError {
+                field: Field::Volume,
+                message: "must be at least 1".into(),
+            });
+        }
+    }
+    errors
+}
+
+/// A preferred or alternate name of an author.
+pub fn check_author_name(name: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    text(&mut errors, Field::Name, name, MAX_AUTHOR_LEN, true);
+    errors
+}
+
+/// Also checks that the reviewed book exists.
+pub fn check_review(conn: &mut PgConnection, review: &NewReview) -> Result<Vec<FieldError>, Error> {
+    let mut errors = Vec::new();
+    isbn(&mut errors, review.isbn);
+    if errors.is_empty()
+        && !diesel::select(exists(books::table.find(review.isbn))).get_result::<bool>(conn)?
+    {
+        errors.push(FieldError {
+            field: Field::Isbn,
+            message: format!("{} is not in the catalog", review.isbn.hyphenated()),
+        });
+    }
+    text(
+        &mut errors,
+        Field::Username,
+        review.username,
+        MAX_USERNAME_LEN,
+        true,
+    );
+    errors.extend(check_review_text(review.description));
+    Ok(errors)
+}
+
+/// The part of a review that can be changed later.
+pub fn check_review_text(description: &str) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+    text(
+        &mut errors,
+        Field::Description,
+        description,
+        MAX_DESCRIPTION_LEN,
+        true,
+    );
+    errors
+}
+
+/// Also checks that the original language is in the `languages` table.
+pub fn check_work(conn: &mut PgConnection, work: &NewWork) -> Result<Vec<FieldError>, Error> {
+    let mut errors = Vec::new();
+    text(&mut errors, Field::Title, &work.title, MAX_TITLE_LEN, true);
+    language(
+        conn,
+        &mut errors,
+        Field::OriginalLanguage,
+        &work.original_language,
+    )?;
+    Ok(errors)
+}
+
+/// Checks that `code` is a [`crate::models::Language::code`].
+pub fn check_language(conn: &mut PgConnection, code: &str) -> Result<Vec<FieldError>, Error> {
+    let mut errors = Vec::new();
+    language(conn, &mut errors, Field::Language, code)?;
+    Ok(errors)
+}
+
+/// Turns the errors of a check into [`Error::Validation`].
+pub fn ensure_valid(errors: Vec<FieldError>) -> Result<(), Error> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Validation(errors))
+    }
+}
+
+/// The error of `field` in an [`Error::Validation`], for showing it next to
+/// the input.
+pub fn field_error(error: &Error, field: Field) -> Option<&FieldError> {
+    match error {
+        Error::Validation(errors) => errors.iter().find(|error| error.field == field),
+        _ => None,
+    }
+}
+
+fn text(errors: &mut Vec<FieldError>, field: Field, value: &str, max: usize, required: bool) {
+    let message = if required && value.trim().is_empty() {
+        "must not be empty".into()
+    } else if value.chars().count() > max {
+        format!("must be at most {max} characters long")
+    } else {
+        return;
+    };
+    errors.push(FieldError { field, message });
+}
+
+fn language(
+    conn: &mut PgConnection,
+    errors: &mut Vec<FieldError>,
+    field: Field,
+    code: &str,
+) -> Result<(), Error> {
+    if !diesel::select(exists(languages::table.find(code))).get_result::<bool>(conn)? {
+        errors.push(FieldError {
+            field,
+            message: format!("`{code}` is not a known language code"),
+        });
+    }
+    Ok(())
+}
+
+/// Books stored before ISBNs were validated can still be read and changed,
+/// but new books and reviews need a valid one.
+fn isbn(errors: &mut Vec<FieldError>, isbn: Isbn) {
+    if !isbn.is_valid() {
+        errors.push(FieldError {
+            field: Field::Isbn,
+            message: format!("{isbn} is not a valid ISBN"),
+        });
+    }
+}
+
+fn issue_year(errors: &mut Vec<FieldError>, year: i32) {
+    let max = max_issue_year();
+    if !(MIN_ISSUE_YEAR..=max).contains(&year) {
+        errors.push(FieldError {
+            field: Field::IssueYear,
+            message: format!("must be between {MIN_ISSUE_YEAR} and {max}"),
+        });
+    }
+}
@@ -0,0 +1,106 @@
+//! Works, which group the books that are editions or translations of the
+//! same text.
+//!
+//! A book belongs to at most one work through `books.work_id`. Books outside
+//! any work are treated as the only edition of their text.
+
+use crate::{
+    models::{Book, Isbn, NewWork, Work},
+    schema::{books, works},
+    validation, Error,
+};
+use diesel::{pg::PgConnection, prelude::*};
+
+/// Fails with [`Error::Validation`] if [`validation::check_work`] finds
+/// problems.
+pub(crate) fn create(conn: &mut PgConnection, work: &NewWork) -> Result<Work, Error> {
+    validation::ensure_valid(validation::check_work(conn, work)?)?;
+    Ok(diesel::insert_into(works::table)
+        .values(work)
+        .get_result(conn)?)
+}
+
+pub(crate) fn get(conn: &mut PgConnection, id: i32) -> Result<Work, Error> {
+    Ok(works::table.find(id).first(conn)?)
+}
+
+pub(crate) fn update(conn: &mut PgConnection, id: i32, work: &NewWork) -> Result<Work, Error> {
+    validation::ensure_valid(validation::check_work(conn, work)?)?;
+    Ok(diesel::update(works::table.find(id))
+        .set(work)
+        .get_result(conn)?)
+}
+
+/// The editions stay in the catalog, without a work.
+pub(crate) fn delete(conn: &mut PgConnection, id: i32) -> Result<usize, Error> {
+    Ok(diesel::delete(works::table.find(id)).execute(conn)?)
+}
+
+/// Puts a book in a work, or takes it out of its work with `None`.
+///
+/// Fails with [`Error::NotFound`] if there is no such book and with
+/// [`Error::ForeignKeyViolation`] if there is no such work.
+pub(crate) fn set_work(
+    conn: &mut PgConnection,
+    isbn: Isbn,
+    work_id: Option<i32>,
+) -> Result<Book, Error> {
+    Ok(diesel::update(books::table.find(isbn))
+        .set(books::work_id.eq(work_id))
+        .get_result(conn)?)
+}
+
+/// Editions of the work in circulation, oldest first.
+pub(crate) fn editions(conn: &mut PgConnection, id: i32) -> Result<Vec<Book>, Error> {
+    Ok(books::table
+        .filter(books::work_id.eq(id))
+        .filter(books::archived_at.is_null())
+        .order((books::issue_year, books::isbn))
+        .load(conn)?)
+}
+
+/// Editions in circulation of any of the works, by work and then oldest
+/// first.
+pub(crate) fn editions_of_works(conn: &mut PgConnection, ids: &[i32]) -> Result<Vec<Book>, Error> {
+    Ok(books::table
+        .filter(books::work_id.eq_any(ids))
+        .filter(books::archived_at.is_null())
+        .order((books::work_id, books::issue_year, books::isbn))
+        .load(conn)?)
+}
+
+/// Editions in circulation of the same work as the book, without the book
+/// itself, oldest first. Empty if the book is not in a work.
+pub(crate) fn other_editions(conn: &mut PgConnection, isbn: Isbn) -> Result<Vec<Book>, Error> {
+    let work_id = books::table
+        .find(isbn)
+        .select(books::work_id)
+        .first::<Option<i32>>(conn)?;
+    let Some(work_id) = work_id else {
+        return Ok(Vec::new());
+    };
+    Ok(books::table
+        .filter(books::work_id.eq(work_id))
+        .filter(books::isbn.ne(isbn))
+        .filter(books::archived_at.is_null())
+        .order((books::issue_year, books::isbn))
+        .load(conn)?)
+}
+
+/// The ISBNs of every edition of the book's work, archived ones included,
+/// or only the book's own if it is not in a work.
+pub(crate) fn edition_isbns(conn: &mut PgConnection, isbn: Isbn) -> Result<Vec<Isbn>, Error> {
+    let work_id = books::table
+        .find(isbn)
+        .select(books::work_id)
+        .first::<Option<i32>>(conn)
+        .optional()?
+        .flatten();
+    let Some(work_id) = work_id else {
+        return Ok(vec![isbn]);
+    };
+    Ok(books::table
+        .filter(books::work_id.eq(work_id))
+        .select(books::isbn)
+        .load(conn)?)
+}
@@ -1,95 +1,186 @@
 use db::{
-    create_book, delete_book, establish_connection, get_book, load_books,
-    models::{Book, NewBook},
+    contributors, create_book, establish_blob_store, establish_connection,
+    filter::BookFilter,
+    find_books, get_book, get_book_series, get_contributors, get_editions_of_works,
+    import::{self, Format, ImportOptions, ImportReport},
+    load_books, load_languages, metadata,
+    models::{
+        Book, ContributorRole, DeletePolicy, FileKind, Isbn, Language, NewBook, NewContributor,
+        NewSeriesEntry, ThumbnailSize,
+    },
+    restore_book, search, search_books, set_book_series, set_contributors,
+    storage::{ConsistencyReport, Storage},
+    update_book,
+    validation::{self, Field},
 };
 use diesel::pg::PgConnection;
 use eframe::{
     egui::{
-        CentralPanel, Color32, ColorImage, Context, Grid, Label, ScrollArea, TextureHandle, Ui,
-        Widget,
+        Button, CentralPanel, Color32, ColorImage, ComboBox, Context, Grid, Label, ScrollArea,
+        TextEdit, TextureHandle, Ui, Widget,
     },
     App, Frame,
 };
 use std::{
-    fs::copy,
+    collections::HashMap,
+    fs::{self, File},
+    io,
     path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
-fn load_image<P: AsRef<Path>>(path: P) -> Result<ColorImage, image::ImageError> {
-    let image = image::io::Reader::open(path)?
-        .with_guessed_format()?
-        .decode()?;
+fn load_image(bytes: &[u8]) -> Result<ColorImage, image::ImageError> {
+    let image = image::load_from_memory(bytes)?;
     let size = [image.width() as _, image.height() as _];
     let image_buffer = image.to_rgba8();
     let pixels = image_buffer.as_flat_samples();
     Ok(ColorImage::from_rgba_unmultiplied(size, pixels.as_slice()))
 }
 
-fn parse_isbn(s: &str) -> Option<i64> {
-    let s: [u8; 13] = s.as_bytes().try_into().ok()?;
-    (s.iter().all(|b| b.is_ascii_digit())
-        && s.iter()
-            .enumerate()
-            .map(|(i, b)| if i % 2 == 0 { 1 } else { 3 } * (b - b'0'))
-            .sum::<u8>()
-            % 10
-            == 0)
-        .then(|| {
-            (0..13)
-                .map(|i| 10i64.pow(12 - i as u32) * (s[i] - b'0') as i64)
-                .sum()
+/// English name of the language with the given code, followed by the native
+/// one if it differs.
+fn language_name(languages: &[Language], code: &str) -> String {
+    match languages.iter().find(|language| language.code == code) {
+        Some(language) if language.native_name != language.english_name => {
+            format!("{} ({})", language.english_name, language.native_name)
+        }
+        Some(language) => language.english_name.clone(),
+        None => code.into(),
+    }
+}
+
+/// English names of the languages of `editions` other than `language`, in
+/// the order of the editions, for "also available in". `editions` may
+/// include the book itself.
+fn other_languages(languages: &[Language], language: &str, editions: &[Book]) -> String {
+    let mut codes: Vec<&str> = Vec::new();
+    for edition in editions {
+        if edition.language != language && !codes.contains(&edition.language.as_str()) {
+            codes.push(&edition.language);
+        }
+    }
+    codes
+        .into_iter()
+        .map(|code| match languages.iter().find(|l| l.code == code) {
+            Some(language) => language.english_name.as_str(),
+            None => code,
         })
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
+/// A cover thumbnail ready to show, or why it cannot be shown.
+type Cover = Result<TextureHandle, String>;
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum Tab {
     Create,
     Read,
     Update,
     Delete,
+    Archive,
+    Import,
 }
 
 pub struct Library {
     connection: PgConnection,
+    storage: Storage,
+    /// Result of the startup consistency check, until repaired or dismissed.
+    consistency: Option<Result<ConsistencyReport, db::Error>>,
+    /// The choices of the language dropdowns.
+    languages: Vec<Language>,
     tab: Tab,
     isbn: String,
     title: String,
-    author: String,
+    /// Names and roles in the order the book lists them.
+    contributors: Vec<(String, ContributorRole)>,
     description: String,
+    /// [`Language::code`], empty until one is chosen.
     language: String,
     issue_year: String,
+    /// Title of the series the book belongs to, empty if none.
+    series: String,
+    volume: String,
+    /// Further series of a book being updated, which the form does not show
+    /// but keeps.
+    more_series: Vec<(String, i32)>,
     cover_path: Option<PathBuf>,
     book_path: Option<PathBuf>,
+    /// Cover found in the picked book file, used if no cover is picked.
+    extracted_cover: Option<Vec<u8>>,
+    metadata_failed_error: Option<db::Error>,
     book_created_label_end: Instant,
     book_deleted_label_end: Instant,
-    book_creation_failed_error: Option<diesel::result::Error>,
-    book_find_failed_error: Option<diesel::result::Error>,
-    book_deletion_failed_error: Option<diesel::result::Error>,
+    /// Whether the last book was archived rather than deleted.
+    book_deleted_archived: bool,
+    book_creation_failed_error: Option<db::Error>,
+    book_find_failed_error: Option<db::Error>,
+    book_deletion_failed_error: Option<db::Error>,
     update_instead_of_create: bool,
-    books: Option<(Vec<Book>, Vec<TextureHandle>)>,
+    delete_policy: DeletePolicy,
+    search_query: String,
+    filter_author: String,
+    filter_language: Option<String>,
+    filter_min_year: String,
+    filter_max_year: String,
+    /// Books found on the read tab with their covers and the languages of
+    /// their other editions.
+    books: Option<(Vec<Book>, Vec<Cover>, Vec<String>)>,
+    archived_books: Option<Vec<Book>>,
+    archive_failed_error: Option<db::Error>,
+    file_save_failed_error: Option<db::Error>,
+    import_path: Option<PathBuf>,
+    /// Outcome of the last run on `import_path`, and whether it was a dry run.
+    import_report: Option<Result<(ImportReport, bool), db::Error>>,
 }
 
 impl Default for Library {
     fn default() -> Self {
+        let mut connection = establish_connection();
+        let storage = Storage::new(establish_blob_store());
+        let consistency = match storage.check(&mut connection) {
+            Ok(report) if report.is_consistent() => None,
+            result => Some(result),
+        };
+        let languages = load_languages(&mut connection).expect("failed to load languages");
         Self {
-            connection: establish_connection(),
+            connection,
+            storage,
+            consistency,
+            languages,
             tab: Tab::Create,
             isbn: String::with_capacity(13),
             title: String::with_capacity(64),
-            author: String::with_capacity(64),
+            contributors: vec![(String::new(), ContributorRole::Author)],
             description: String::with_capacity(1024),
-            language: String::with_capacity(16),
+            language: String::with_capacity(3),
             issue_year: String::with_capacity(4),
+            series: String::with_capacity(64),
+            volume: String::with_capacity(2),
+            more_series: Vec::new(),
             cover_path: None,
             book_path: None,
+            extracted_cover: None,
+            metadata_failed_error: None,
             book_created_label_end: Instant::now(),
             book_deleted_label_end: Instant::now(),
+            book_deleted_archived: false,
             book_creation_failed_error: None,
             book_find_failed_error: None,
             book_deletion_failed_error: None,
             update_instead_of_create: false,
+            delete_policy: DeletePolicy::default(),
+            search_query: String::with_capacity(64),
+            filter_author: String::with_capacity(64),
+            filter_language: None,
+            filter_min_year: String::with_capacity(4),
+            filter_max_year: String::with_capacity(4),
             books: None,
+            archived_books: None,
+            archive_failed_error: None,
+            file_save_failed_error: None,
+            import_path: None,
+            import_report: None,
         }
     }
 }
@@ -97,49 +188,80 @@ impl Default for Library {
 impl Library {
     fn create_tab(&mut self, ui: &mut Ui) {
         let now = Instant::now();
-        let isbn = parse_isbn(&self.isbn);
-        let lang = self.language.parse();
+        // an update may be of a book stored before ISBNs were validated
+        let isbn = if self.update_instead_of_create {
+            Isbn::parse_stored(&self.isbn).ok()
+        } else {
+            self.isbn.parse::<Isbn>().ok()
+        };
         let year = self.issue_year.parse();
         let mut button_enabled = true;
+        let previous_book_path = self.book_path.clone();
         let checks = [
             isbn.is_some(),
             !self.title.is_empty(),
-            !self.author.is_empty(),
-            lang.is_ok(),
+            self.languages
+                .iter()
+                .any(|language| language.code == self.language),
             year.is_ok(),
             !self.description.is_empty(),
         ];
         Grid::new("grid_of_inputs").show(ui, |ui| {
-            for ((label, var, singleline), check) in [
-                ("ISBN-13", &mut self.isbn, true),
-                ("title", &mut self.title, true),
-                ("author", &mut self.author, true),
-                ("language", &mut self.language, true),
-                ("issue year", &mut self.issue_year, true),
-                ("description", &mut self.description, false),
+            for ((label, var, singleline, field), check) in [
+                ("ISBN", &mut self.isbn, true, Field::Isbn),
+                ("title", &mut self.title, true, Field::Title),
+                ("language", &mut self.language, true, Field::Language),
+                ("issue year", &mut self.issue_year, true, Field::IssueYear),
+                ("description", &mut self.description, false, Field::Description),
             ]
             .into_iter()
             .zip(checks)
             {
-                let label = if check {
+                // rejected by the last attempt to save
+                let rejected = self
+                    .book_creation_failed_error
+                    .as_ref()
+                    .and_then(|e| validation::field_error(e, field));
+                let label = if check && rejected.is_none() {
                     ui.label(label)
                 } else {
-                    button_enabled = false;
+                    button_enabled &= check;
                     ui.colored_label(ui.visuals().error_fg_color, label)
                 };
-                if singleline {
+                if field == Field::Language {
+                    ComboBox::from_id_source("language")
+                        .selected_text(language_name(&self.languages, var))
+                        .show_ui(ui, |ui| {
+                            for language in &self.languages {
+                                ui.selectable_value(
+                                    var,
+                                    language.code.clone(),
+                                    language_name(&self.languages, &language.code),
+                                );
+                            }
+                        })
+                        .response
+                } else if singleline {
                     ui.text_edit_singleline(var)
                 } else {
                     ui.text_edit_multiline(var)
                 }
                 .labelled_by(label.id);
+                if let Some(rejected) = rejected {
+                    ui.colored_label(ui.visuals().error_fg_color, &rejected.message);
+                }
                 ui.end_row();
             }
-            for (label, path_var) in [
-                ("cover", &mut self.cover_path),
-                ("book file", &mut self.book_path),
+            self.contributors_row(ui, &mut button_enabled);
+            self.series_row(ui, &mut button_enabled);
+            // an update keeps the stored files unless new ones are picked
+            let keep_files = self.update_instead_of_create;
+            let extracted_cover = self.extracted_cover.is_some();
+            for (label, path_var, extracted) in [
+                ("cover", &mut self.cover_path, extracted_cover),
+                ("book file", &mut self.book_path, false),
             ] {
-                let label = if path_var.is_some() {
+                let label = if path_var.is_some() || extracted || keep_files {
                     ui.label(label)
                 } else {
                     button_enabled = false;
@@ -153,6 +275,10 @@ impl Library {
                     }
                     if let Some(path) = path_var {
                         ui.label(path.to_str().unwrap_or("???"));
+                    } else if extracted {
+                        ui.label("from book file");
+                    } else if keep_files {
+                        ui.label("keep current");
                     }
                 });
                 ui.end_row();
@@ -161,6 +287,17 @@ impl Library {
             ui.label("                                                                                        ");
             ui.end_row();
         });
+        if self.book_path != previous_book_path {
+            if let Some(path) = self.book_path.clone() {
+                self.prefill(&path);
+            }
+        }
+        if let Some(e) = &self.metadata_failed_error {
+            ui.colored_label(
+                ui.visuals().error_fg_color,
+                format!("failed to read metadata from the book file: {e}"),
+            );
+        }
         ui.horizontal(|ui| {
             ui.scope(|ui| {
                 ui.set_enabled(button_enabled);
@@ -173,56 +310,72 @@ impl Library {
                     .clicked()
                 {
                     let isbn = isbn.unwrap();
-                    let mut failed_to_delete = false;
-                    if self.update_instead_of_create {
-                        match delete_book(&mut self.connection, isbn) {
-                            Err(e) => {
-                                self.book_created_label_end = now;
-                                self.book_creation_failed_error = Some(e);
-                                failed_to_delete = true;
-                            }
-                            Ok(0) => {
-                                self.book_created_label_end = now;
-                                self.book_creation_failed_error =
-                                    Some(diesel::result::Error::NotFound);
-                                failed_to_delete = true;
-                            }
-                            _ => {
-                                self.book_created_label_end = now + Duration::from_secs(3);
-                                self.book_creation_failed_error = None;
-                            }
+                    let contributors: Vec<NewContributor> = self
+                        .contributors
+                        .iter()
+                        .map(|(name, role)| NewContributor {
+                            name: name.as_str().into(),
+                            role: *role,
+                        })
+                        .collect();
+                    let volume = self.volume.parse().unwrap_or_default();
+                    let series: Vec<NewSeriesEntry> = (!self.series.trim().is_empty())
+                        .then(|| NewSeriesEntry {
+                            title: self.series.as_str().into(),
+                            volume,
+                        })
+                        .into_iter()
+                        .chain(
+                            self.more_series
+                                .iter()
+                                .map(|(title, volume)| NewSeriesEntry {
+                                    title: title.as_str().into(),
+                                    volume: *volume,
+                                }),
+                        )
+                        .collect();
+                    let book = NewBook {
+                        isbn,
+                        title: self.title.as_str().into(),
+                        author: contributors::byline(&contributors).into(),
+                        description: self.description.as_str().into(),
+                        language: self.language.as_str().into(),
+                        issue_year: year.unwrap(),
+                    };
+                    let update = self.update_instead_of_create;
+                    let mut files = self.storage.transaction();
+                    let result = [
+                        (FileKind::Cover, &self.cover_path),
+                        (FileKind::Book, &self.book_path),
+                    ]
+                    .into_iter()
+                    .filter_map(|(kind, path)| Some((kind, path.as_ref()?)))
+                    .try_for_each(|(kind, path)| files.stage_copy(kind, isbn, path))
+                    .and_then(|()| match (&self.cover_path, &self.extracted_cover) {
+                        (None, Some(cover)) => {
+                            files.stage(FileKind::Cover, isbn, &mut cover.as_slice())
                         }
+                        _ => Ok(()),
+                    })
+                    .and_then(|()| {
+                        files.run(&mut self.connection, |conn| {
+                            if update {
+                                update_book(conn, isbn, &book.into()).map(drop)
+                            } else {
+                                create_book(conn, &book).map(drop)
+                            }
+                            .and_then(|()| set_contributors(conn, isbn, &contributors).map(drop))
+                            .and_then(|()| set_book_series(conn, isbn, &series).map(drop))
+                        })
+                    });
+                    if let Err(e) = result {
+                        self.book_created_label_end = now;
+                        self.book_creation_failed_error = Some(e);
+                    } else {
+                        self.book_created_label_end = now + Duration::from_secs(3);
+                        self.book_creation_failed_error = None;
                         self.update_instead_of_create = false;
-                    }
-
-                    if !failed_to_delete {
-                        let from = self.cover_path.as_ref().unwrap();
-                        let to: PathBuf = format!("covers/{}", self.isbn).into();
-                        if from != &to {
-                            copy(from, to).unwrap();
-                        }
-                        let from = self.book_path.as_ref().unwrap();
-                        let to: PathBuf = format!("books/{}", self.isbn).into();
-                        if from != &to {
-                            copy(from, to).unwrap();
-                        }
-                        if let Err(e) = create_book(
-                            &mut self.connection,
-                            &NewBook {
-                                isbn,
-                                title: &self.title,
-                                author: &self.author,
-                                description: &self.description,
-                                language: lang.unwrap(),
-                                issue_year: year.unwrap(),
-                            },
-                        ) {
-                            self.book_created_label_end = now;
-                            self.book_creation_failed_error = Some(e);
-                        } else {
-                            self.book_created_label_end = now + Duration::from_secs(3);
-                            self.book_creation_failed_error = None;
-                        }
+                        self.more_series.clear();
                     }
                 }
             });
@@ -238,19 +391,253 @@ impl Library {
         });
     }
 
+    /// The list of contributors in the create form, each with a role and
+    /// buttons to move it up or remove it.
+    fn contributors_row(&mut self, ui: &mut Ui, button_enabled: &mut bool) {
+        let check = self
+            .contributors
+            .iter()
+            .all(|(name, _)| !name.trim().is_empty())
+            && self
+                .contributors
+                .iter()
+                .any(|(_, role)| *role == ContributorRole::Author);
+        let rejected = self.book_creation_failed_error.as_ref().and_then(|e| {
+            validation::field_error(e, Field::Contributors)
+                .or_else(|| validation::field_error(e, Field::Author))
+        });
+        let label = if check && rejected.is_none() {
+            ui.label("contributors")
+        } else {
+            *button_enabled &= check;
+            ui.colored_label(ui.visuals().error_fg_color, "contributors")
+        };
+        let mut moved_up = None;
+        let mut removed = None;
+        ui.vertical(|ui| {
+            for (i, (name, role)) in self.contributors.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(name).labelled_by(label.id);
+                    ComboBox::from_id_source(("contributor_role", i))
+                        .selected_text(role.as_str())
+                        .show_ui(ui, |ui| {
+                            for choice in ContributorRole::ALL {
+                                ui.selectable_value(role, choice, choice.as_str());
+                            }
+                        });
+                    if ui.add_enabled(i > 0, Button::new("up")).clicked() {
+                        moved_up = Some(i);
+                    }
+                    if ui.button("remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            }
+            if ui.button("add contributor").clicked() {
+                self.contributors
+                    .push((String::new(), ContributorRole::Author));
+            }
+        });
+        if let Some(i) = moved_up {
+            self.contributors.swap(i - 1, i);
+        }
+        if let Some(i) = removed {
+            self.contributors.remove(i);
+        }
+        if let Some(rejected) = rejected {
+            ui.colored_label(ui.visuals().error_fg_color, &rejected.message);
+        }
+        ui.end_row();
+    }
+
+    /// The series of the book in the create form and its volume number in
+    /// it, which is needed once a series is given.
+    fn series_row(&mut self, ui: &mut Ui, button_enabled: &mut bool) {
+        let check = self.series.trim().is_empty()
+            || self.volume.parse::<i32>().is_ok_and(|volume| volume >= 1);
+        let rejected = self.book_creation_failed_error.as_ref().and_then(|e| {
+            validation::field_error(e, Field::Series)
+                .or_else(|| validation::field_error(e, Field::Volume))
+        });
+        let label = if check && rejected.is_none() {
+            ui.label("series")
+        } else {
+            *button_enabled &= check;
+            ui.colored_label(ui.visuals().error_fg_color, "series")
+        };
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.series)
+                .labelled_by(label.id);
+            ui.label("volume");
+            ui.add(TextEdit::singleline(&mut self.volume).desired_width(48.))
+                .labelled_by(label.id);
+        });
+        if let Some(rejected) = rejected {
+            ui.colored_label(ui.visuals().error_fg_color, &rejected.message);
+        }
+        ui.end_row();
+    }
+
+    /// Fills the empty fields of the create form with what the book file
+    /// says about itself.
+    fn prefill(&mut self, path: &Path) {
+        self.extracted_cover = None;
+        let metadata = match metadata::extract_file(path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                self.metadata_failed_error = Some(e);
+                return;
+            }
+        };
+        self.metadata_failed_error = None;
+        for (var, value) in [
+            (&mut self.isbn, metadata.isbn.map(Isbn::hyphenated)),
+            (&mut self.title, metadata.title),
+            (&mut self.description, metadata.description),
+            (
+                &mut self.language,
+                metadata
+                    .language
+                    .and_then(|tag| Language::find(&self.languages, &tag))
+                    .map(|language| language.code.clone()),
+            ),
+            (
+                &mut self.issue_year,
+                metadata.issue_year.map(|year| year.to_string()),
+            ),
+        ] {
+            if let (true, Some(value)) = (var.is_empty(), value) {
+                *var = value;
+            }
+        }
+        if self.contributors.iter().all(|(name, _)| name.is_empty()) {
+            if let Some(author) = metadata.author {
+                self.contributors = contributors::split_names(&author)
+                    .into_iter()
+                    .map(|name| (name, ContributorRole::Author))
+                    .collect();
+            }
+        }
+        self.extracted_cover = metadata.cover;
+    }
+
+    fn read_filter(&self) -> Option<BookFilter> {
+        let parse_year = |s: &str| {
+            if s.is_empty() {
+                Some(None)
+            } else {
+                s.parse().ok().map(Some)
+            }
+        };
+        Some(BookFilter {
+            language: self.filter_language.clone(),
+            min_year: parse_year(&self.filter_min_year)?,
+            max_year: parse_year(&self.filter_max_year)?,
+            author: (!self.filter_author.is_empty()).then(|| self.filter_author.clone()),
+            archived: false,
+        })
+    }
+
     fn read_tab(&mut self, ui: &mut Ui) {
+        let filter = self.read_filter();
+        Grid::new("grid_of_filters").show(ui, |ui| {
+            let label = ui.label("search");
+            ui.text_edit_singleline(&mut self.search_query)
+                .labelled_by(label.id);
+            ui.end_row();
+            let label = ui.label("author");
+            ui.text_edit_singleline(&mut self.filter_author)
+                .labelled_by(label.id);
+            ui.end_row();
+            ui.label("language");
+            ComboBox::from_id_source("filter_language")
+                .selected_text(
+                    self.filter_language
+                        .as_ref()
+                        .map_or_else(|| "any".into(), |code| language_name(&self.languages, code)),
+                )
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.filter_language, None, "any");
+                    for language in &self.languages {
+                        ui.selectable_value(
+                            &mut self.filter_language,
+                            Some(language.code.clone()),
+                            language_name(&self.languages, &language.code),
+                        );
+                    }
+                });
+            ui.end_row();
+            let label = if filter.is_some() {
+                ui.label("issue years")
+            } else {
+                ui.colored_label(ui.visuals().error_fg_color, "issue years")
+            };
+            ui.horizontal(|ui| {
+                ui.add(TextEdit::singleline(&mut self.filter_min_year).desired_width(48.))
+                    .labelled_by(label.id);
+                ui.label("to");
+                ui.add(TextEdit::singleline(&mut self.filter_max_year).desired_width(48.))
+                    .labelled_by(label.id);
+            });
+            ui.end_row();
+            ui.label("");
+            ui.scope(|ui| {
+                ui.set_enabled(filter.is_some());
+                if ui.button("search").clicked() {
+                    self.books = None;
+                }
+            });
+            ui.end_row();
+        });
         if self.books.is_none() {
-            match load_books(&mut self.connection) {
+            let filter = filter.unwrap_or_default();
+            let books = if !self.search_query.trim().is_empty() {
+                search_books(
+                    &mut self.connection,
+                    &self.search_query,
+                    &filter,
+                    search::MAX_RESULTS,
+                )
+                .map(|hits| hits.into_iter().map(|hit| hit.book).collect())
+            } else if filter.is_empty() {
+                load_books(&mut self.connection)
+            } else {
+                find_books(&mut self.connection, &filter)
+            };
+            match books {
                 Ok(books) => {
+                    let work_ids: Vec<i32> = books.iter().filter_map(|book| book.work_id).collect();
+                    let mut editions: HashMap<i32, Vec<Book>> = HashMap::new();
+                    for edition in
+                        get_editions_of_works(&mut self.connection, &work_ids).unwrap_or_default()
+                    {
+                        if let Some(work_id) = edition.work_id {
+                            editions.entry(work_id).or_default().push(edition);
+                        }
+                    }
                     let mut texture_handles = Vec::with_capacity(books.capacity());
+                    let mut translations = Vec::with_capacity(books.capacity());
                     for book in &books {
-                        texture_handles.push(ui.ctx().load_texture(
-                            "cover",
-                            load_image(&format!("covers/{}", book.isbn)).unwrap(),
-                            Default::default(),
+                        let editions = book
+                            .work_id
+                            .and_then(|work_id| editions.get(&work_id))
+                            .map_or(&[][..], Vec::as_slice);
+                        translations.push(other_languages(
+                            &self.languages,
+                            &book.language,
+                            editions,
                         ));
+                        // books created over REST or imported may have no cover
+                        let texture = self
+                            .storage
+                            .file(&mut self.connection, FileKind::Cover, book.isbn)
+                            .and_then(|cover| self.storage.thumbnail(&cover, ThumbnailSize::Medium))
+                            .map_err(|e| e.to_string())
+                            .and_then(|bytes| load_image(&bytes).map_err(|e| e.to_string()))
+                            .map(|image| ui.ctx().load_texture("cover", image, Default::default()));
+                        texture_handles.push(texture);
                     }
-                    self.books = Some((books, texture_handles));
+                    self.books = Some((books, texture_handles, translations));
                 }
                 Err(e) => {
                     ui.colored_label(
@@ -261,31 +648,62 @@ impl Library {
                 }
             }
         };
-        let (books, texture_handles) = self.books.as_ref().unwrap();
+        if let Some(e) = &self.file_save_failed_error {
+            ui.colored_label(
+                ui.visuals().error_fg_color,
+                format!("failed to save book file: {e}"),
+            );
+        }
+        let (books, texture_handles, translations) = self.books.as_ref().unwrap();
+        let storage = &self.storage;
+        let connection = &mut self.connection;
+        let languages = &self.languages;
+        let mut saved = None;
         ScrollArea::vertical().show(ui, |ui| {
-            for ((id, book), texture) in (1337..).zip(books).zip(texture_handles) {
+            for (((id, book), texture), translations) in
+                (1337..).zip(books).zip(texture_handles).zip(translations)
+            {
                 ui.group(|ui| {
                     ui.horizontal(|ui| {
-                        ui.image(texture, texture.size_vec2());
+                        match texture {
+                            Ok(texture) => ui.image(texture, texture.size_vec2()),
+                            Err(e) => ui.colored_label(
+                                ui.visuals().error_fg_color,
+                                format!("no cover: {e}"),
+                            ),
+                        };
                         Grid::new(id).show(ui, |ui| {
                             for (label, val) in [
-                                ("ISBN-13", &book.isbn.to_string() as &str),
+                                ("ISBN", &book.isbn.hyphenated() as &str),
                                 ("title", &book.title),
                                 ("author", &book.author),
-                                ("language", book.language.to_str()),
+                                ("language", &language_name(languages, &book.language)),
                                 ("issue year", &book.issue_year.to_string()),
                             ] {
                                 ui.label(label);
                                 ui.label(val);
                                 ui.end_row();
                             }
+                            if !translations.is_empty() {
+                                ui.label("also available in");
+                                ui.label(translations);
+                                ui.end_row();
+                            }
                             ui.label("description");
                             Label::new(&book.description).wrap(true).ui(ui);
                             ui.end_row();
                             ui.label("book file");
                             if ui.button("save file...").clicked() {
                                 if let Some(path) = rfd::FileDialog::new().save_file() {
-                                    copy(format!("books/{}", book.isbn), path).unwrap();
+                                    saved = Some(
+                                        storage
+                                            .file(connection, FileKind::Book, book.isbn)
+                                            .and_then(|stored| {
+                                                let mut file = File::create(path)?;
+                                                io::copy(&mut storage.open(&stored)?, &mut file)?;
+                                                Ok(())
+                                            }),
+                                    );
                                 }
                             }
                         });
@@ -293,15 +711,18 @@ impl Library {
                 });
             }
         });
+        if let Some(result) = saved {
+            self.file_save_failed_error = result.err();
+        }
     }
 
     fn update_tab(&mut self, ui: &mut Ui) {
-        let isbn = parse_isbn(&self.isbn);
+        let isbn = Isbn::parse_stored(&self.isbn).ok();
         ui.horizontal(|ui| {
             let label = if isbn.is_some() {
-                ui.label("ISBN-13")
+                ui.label("ISBN")
             } else {
-                ui.colored_label(ui.visuals().error_fg_color, "ISBN-13")
+                ui.colored_label(ui.visuals().error_fg_color, "ISBN")
             };
             ui.text_edit_singleline(&mut self.isbn)
                 .labelled_by(label.id);
@@ -311,18 +732,37 @@ impl Library {
                 ui.set_enabled(isbn.is_some());
                 if ui.button("update book").clicked() {
                     let isbn = isbn.unwrap();
-                    match get_book(&mut self.connection, isbn) {
-                        Ok(book) => {
+                    let conn = &mut self.connection;
+                    match get_book(conn, isbn).and_then(|book| {
+                        Ok((
+                            book,
+                            get_contributors(conn, isbn)?,
+                            get_book_series(conn, isbn)?,
+                        ))
+                    }) {
+                        Ok((book, contributors, series)) => {
                             self.book_find_failed_error = None;
                             self.tab = Tab::Create;
                             self.update_instead_of_create = true;
                             self.title = book.title;
-                            self.author = book.author;
-                            self.language = book.language.to_str().into();
+                            self.contributors = contributors
+                                .into_iter()
+                                .map(|contributor| (contributor.name, contributor.role))
+                                .collect();
+                            let mut series =
+                                series.into_iter().map(|entry| (entry.title, entry.volume));
+                            (self.series, self.volume) = series
+                                .next()
+                                .map(|(title, volume)| (title, volume.to_string()))
+                                .unwrap_or_default();
+                            self.more_series = series.collect();
+                            self.language = book.language;
                             self.issue_year = book.issue_year.to_string();
                             self.description = book.description;
-                            self.cover_path = Some(format!("covers/{}", self.isbn).into());
-                            self.book_path = Some(format!("books/{}", self.isbn).into());
+                            self.cover_path = None;
+                            self.book_path = None;
+                            self.extracted_cover = None;
+                            self.metadata_failed_error = None;
                         }
                         Err(e) => {
                             self.book_find_failed_error = Some(e);
@@ -341,39 +781,55 @@ impl Library {
 
     fn delete_tab(&mut self, ui: &mut Ui) {
         let now = Instant::now();
-        let isbn = parse_isbn(&self.isbn);
+        let isbn = Isbn::parse_stored(&self.isbn).ok();
         ui.horizontal(|ui| {
             let label = if isbn.is_some() {
-                ui.label("ISBN-13")
+                ui.label("ISBN")
             } else {
-                ui.colored_label(ui.visuals().error_fg_color, "ISBN-13")
+                ui.colored_label(ui.visuals().error_fg_color, "ISBN")
             };
             ui.text_edit_singleline(&mut self.isbn)
                 .labelled_by(label.id);
         });
+        ui.horizontal(|ui| {
+            ui.label("reviews");
+            for (policy, text) in [
+                (DeletePolicy::Restrict, "refuse if any"),
+                (DeletePolicy::Cascade, "delete with the book"),
+                (DeletePolicy::Archive, "archive the book instead"),
+            ] {
+                ui.radio_value(&mut self.delete_policy, policy, text);
+            }
+        });
         ui.horizontal(|ui| {
             ui.scope(|ui| {
                 ui.set_enabled(isbn.is_some());
                 if ui.button("delete book").clicked() {
                     let isbn = isbn.unwrap();
-                    match delete_book(&mut self.connection, isbn) {
+                    match self
+                        .storage
+                        .delete_book(&mut self.connection, isbn, self.delete_policy)
+                    {
                         Err(e) => {
                             self.book_deleted_label_end = now;
                             self.book_deletion_failed_error = Some(e);
                         }
-                        Ok(0) => {
-                            self.book_deleted_label_end = now;
-                            self.book_deletion_failed_error = Some(diesel::result::Error::NotFound);
-                        }
-                        _ => {
+                        Ok(_) => {
                             self.book_deleted_label_end = now + Duration::from_secs(3);
+                            self.book_deleted_archived =
+                                self.delete_policy == DeletePolicy::Archive;
                             self.book_deletion_failed_error = None;
                         }
                     }
                 }
             });
             if self.book_deleted_label_end > now {
-                ui.colored_label(Color32::from_rgb(119, 221, 119), "book deleted!");
+                let text = if self.book_deleted_archived {
+                    "book archived!"
+                } else {
+                    "book deleted!"
+                };
+                ui.colored_label(Color32::from_rgb(119, 221, 119), text);
             }
             if let Some(e) = &self.book_deletion_failed_error {
                 ui.colored_label(
@@ -383,6 +839,209 @@ impl Library {
             }
         });
     }
+
+    fn archive_tab(&mut self, ui: &mut Ui) {
+        if self.archived_books.is_none() {
+            match find_books(&mut self.connection, &BookFilter::new().archived()) {
+                Ok(books) => self.archived_books = Some(books),
+                Err(e) => {
+                    ui.colored_label(
+                        ui.visuals().error_fg_color,
+                        format!("failed to load archived books: {e}"),
+                    );
+                    return;
+                }
+            }
+        }
+        if let Some(e) = &self.archive_failed_error {
+            ui.colored_label(
+                ui.visuals().error_fg_color,
+                format!("failed to update book: {e}"),
+            );
+        }
+        let mut clicked = None;
+        ScrollArea::vertical().show(ui, |ui| {
+            Grid::new("grid_of_archived_books").show(ui, |ui| {
+                for book in self.archived_books.as_ref().unwrap() {
+                    ui.label(book.isbn.hyphenated());
+                    ui.label(&book.title);
+                    ui.label(&book.author);
+                    if ui.button("restore").clicked() {
+                        clicked = Some((book.isbn, false));
+                    }
+                    if ui.button("delete permanently").clicked() {
+                        clicked = Some((book.isbn, true));
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+        if let Some((isbn, purge)) = clicked {
+            let result = if purge {
+                self.storage
+                    .delete_book(&mut self.connection, isbn, DeletePolicy::Cascade)
+            } else {
+                restore_book(&mut self.connection, isbn).map(drop)
+            };
+            match result {
+                Ok(_) => {
+                    self.archive_failed_error = None;
+                    self.archived_books = None;
+                }
+                Err(e) => self.archive_failed_error = Some(e),
+            }
+        }
+    }
+
+    /// Picking a file, checking it with a dry run, then importing it.
+    fn import_tab(&mut self, ui: &mut Ui) {
+        let format = self.import_path.as_deref().and_then(Format::from_path);
+        ui.horizontal(|ui| {
+            if ui.button("1. open file...").clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("books", &["csv", "json", "jsonl", "mrc", "marc", "xml"])
+                    .pick_file()
+                {
+                    self.import_path = Some(path);
+                    self.import_report = None;
+                }
+            }
+            if let Some(path) = &self.import_path {
+                ui.label(path.to_str().unwrap_or("???"));
+            } else {
+                ui.label(
+                    "CSV or JSON with isbn, title, author, description, language and issue_year, \
+                     or MARC 21",
+                );
+            }
+        });
+        if self.import_path.is_some() && format.is_none() {
+            ui.colored_label(
+                ui.visuals().error_fg_color,
+                "only .csv, .json, .jsonl, .mrc and .xml files can be imported",
+            );
+        }
+        let checked = matches!(self.import_report, Some(Ok((_, true))));
+        let mut dry_run = None;
+        ui.horizontal(|ui| {
+            ui.scope(|ui| {
+                ui.set_enabled(format.is_some());
+                if ui.button("2. check").clicked() {
+                    dry_run = Some(true);
+                }
+            });
+            ui.scope(|ui| {
+                ui.set_enabled(checked);
+                if ui.button("3. import").clicked() {
+                    dry_run = Some(false);
+                }
+            });
+        });
+        if let (Some(dry_run), Some(format), Some(path)) = (dry_run, format, &self.import_path) {
+            let options = ImportOptions {
+                dry_run,
+                ..Default::default()
+            };
+            let result = fs::read(path)
+                .map_err(db::Error::from)
+                .and_then(|data| import::import(&mut self.connection, format, &data, &options));
+            self.import_report = Some(result.map(|report| (report, dry_run)));
+        }
+        match &self.import_report {
+            None => {}
+            Some(Err(e)) => {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("failed to import books: {e}"),
+                );
+            }
+            Some(Ok((report, dry_run))) => {
+                if *dry_run {
+                    ui.label(format!(
+                        "{} of {} rows can be imported",
+                        report.imported, report.rows
+                    ));
+                } else {
+                    ui.colored_label(
+                        Color32::from_rgb(119, 221, 119),
+                        format!("imported {} of {} rows", report.imported, report.rows),
+                    );
+                }
+                ScrollArea::vertical().show(ui, |ui| {
+                    Grid::new("grid_of_import_errors").show(ui, |ui| {
+                        for error in &report.errors {
+                            ui.label(format!("row {}", error.row));
+                            ui.label(error.field.unwrap_or(""));
+                            ui.colored_label(ui.visuals().error_fg_color, &error.message);
+                            ui.end_row();
+                        }
+                    });
+                });
+            }
+        }
+    }
+
+    fn consistency_banner(&mut self, ui: &mut Ui) {
+        let mut close = false;
+        let mut repair_error = None;
+        match &self.consistency {
+            None => return,
+            Some(Err(e)) => {
+                ui.horizontal(|ui| {
+                    ui.colored_label(
+                        ui.visuals().error_fg_color,
+                        format!("failed to check book files: {e}"),
+                    );
+                    close = ui.button("dismiss").clicked();
+                });
+            }
+            Some(Ok(report)) => {
+                ui.group(|ui| {
+                    for key in &report.orphaned_files {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!("file without a book: {key}"),
+                        );
+                    }
+                    for key in &report.legacy_files {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!("file in the old layout: {key}"),
+                        );
+                    }
+                    for file in &report.missing_files {
+                        let kind = match file.kind {
+                            FileKind::Cover => "cover",
+                            FileKind::Book => "book file",
+                        };
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!("{kind} of book {} is missing from storage", file.isbn),
+                        );
+                    }
+                    ui.horizontal(|ui| {
+                        if ui
+                            .button("forget missing files, adopt old ones and move stray ones to orphaned/")
+                            .clicked()
+                        {
+                            match self.storage.repair(&mut self.connection, report) {
+                                Ok(()) => close = true,
+                                Err(e) => repair_error = Some(e),
+                            }
+                        }
+                        if ui.button("dismiss").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            }
+        }
+        if let Some(e) = repair_error {
+            self.consistency = Some(Err(e));
+        } else if close {
+            self.consistency = None;
+        }
+    }
 }
 
 impl App for Library {
@@ -394,16 +1053,24 @@ impl App for Library {
                 ui.selectable_value(&mut self.tab, Tab::Read, "read");
                 ui.selectable_value(&mut self.tab, Tab::Update, "update");
                 ui.selectable_value(&mut self.tab, Tab::Delete, "delete");
+                ui.selectable_value(&mut self.tab, Tab::Archive, "archive");
+                ui.selectable_value(&mut self.tab, Tab::Import, "import");
             });
+            self.consistency_banner(ui);
 
             if self.tab != Tab::Read {
                 self.books = None;
             }
+            if self.tab != Tab::Archive {
+                self.archived_books = None;
+            }
             match self.tab {
                 Tab::Create => self.create_tab(ui),
                 Tab::Read => self.read_tab(ui),
                 Tab::Update => self.update_tab(ui),
                 Tab::Delete => self.delete_tab(ui),
+                Tab::Archive => self.archive_tab(ui),
+                Tab::Import => self.import_tab(ui),
             }
         });
     }
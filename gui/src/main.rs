@@ -3,13 +3,10 @@
 use dotenvy::dotenv;
 use eframe::egui;
 use gui::Library;
-use std::fs::create_dir_all;
 
 fn main() -> Result<(), eframe::Error> {
     dotenv().ok();
     env_logger::init();
-    create_dir_all("books").unwrap();
-    create_dir_all("covers").unwrap();
     eframe::run_native(
         "Library",
         eframe::NativeOptions {
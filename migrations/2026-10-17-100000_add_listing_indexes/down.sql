@@ -0,0 +1,7 @@
+DROP INDEX reviews_username_rating;
+DROP INDEX reviews_username_created_at;
+DROP INDEX reviews_isbn_rating;
+DROP INDEX reviews_isbn_created_at;
+DROP INDEX books_issue_year_isbn;
+DROP INDEX books_author_isbn;
+DROP INDEX books_title_isbn;
@@ -0,0 +1,7 @@
+CREATE INDEX books_title_isbn ON books (title, isbn);
+CREATE INDEX books_author_isbn ON books (author, isbn);
+CREATE INDEX books_issue_year_isbn ON books (issue_year, isbn);
+CREATE INDEX reviews_isbn_created_at ON reviews (isbn, created_at, username);
+CREATE INDEX reviews_isbn_rating ON reviews (isbn, rating, username);
+CREATE INDEX reviews_username_created_at ON reviews (username, created_at, isbn);
+CREATE INDEX reviews_username_rating ON reviews (username, rating, isbn);
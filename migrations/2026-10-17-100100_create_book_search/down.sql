@@ -0,0 +1,5 @@
+DROP TRIGGER book_search_update ON books;
+DROP FUNCTION book_search_update();
+DROP FUNCTION book_search_document(books);
+DROP TABLE book_search;
+DROP FUNCTION lang_search_config(lang);
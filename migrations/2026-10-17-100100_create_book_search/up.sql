@@ -0,0 +1,37 @@
+-- Text search configuration used to stem a book written in the given language.
+-- Languages without a built-in configuration fall back to `simple`.
+CREATE FUNCTION lang_search_config(lang) RETURNS regconfig AS $$
+    SELECT CASE $1
+        WHEN 'english' THEN 'english'::regconfig
+        WHEN 'russian' THEN 'russian'::regconfig
+        WHEN 'german' THEN 'german'::regconfig
+        ELSE 'simple'::regconfig
+    END
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE TABLE book_search (
+    isbn bigint primary key references books(isbn) on delete cascade,
+    document tsvector not null
+);
+CREATE INDEX book_search_document ON book_search USING gin (document);
+
+CREATE FUNCTION book_search_document(books) RETURNS tsvector AS $$
+    SELECT setweight(to_tsvector(lang_search_config($1.language), $1.title), 'A')
+        || setweight(to_tsvector(lang_search_config($1.language), $1.author), 'B')
+        || setweight(to_tsvector(lang_search_config($1.language), $1.description), 'C')
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE FUNCTION book_search_update() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO book_search (isbn, document)
+    VALUES (NEW.isbn, book_search_document(NEW))
+    ON CONFLICT (isbn) DO UPDATE SET document = EXCLUDED.document;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER book_search_update AFTER INSERT OR UPDATE ON books
+    FOR EACH ROW EXECUTE PROCEDURE book_search_update();
+
+INSERT INTO book_search (isbn, document)
+SELECT isbn, book_search_document(books) FROM books;
@@ -0,0 +1,2 @@
+DROP TABLE book_files;
+DROP TYPE file_kind;
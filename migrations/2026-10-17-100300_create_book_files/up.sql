@@ -0,0 +1,11 @@
+CREATE TYPE file_kind AS ENUM ('cover', 'book');
+CREATE TABLE book_files (
+    isbn bigint not null references books(isbn) on delete cascade,
+    kind file_kind not null,
+    primary key (isbn, kind),
+    -- hex-encoded SHA-256 of the contents, which is also the blob's key
+    sha256 text not null check (sha256 ~ '^[0-9a-f]{64}$'),
+    size bigint not null,
+    mime text not null
+);
+CREATE INDEX book_files_sha256_idx ON book_files (sha256);
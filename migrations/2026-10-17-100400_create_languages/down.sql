@@ -0,0 +1,37 @@
+DROP AGGREGATE tsquery_or_agg(tsquery);
+DROP FUNCTION book_search_document(books);
+DROP FUNCTION language_search_config(varchar);
+
+CREATE TYPE lang AS ENUM ('english', 'russian', 'ukrainian', 'german', 'chinese', 'japanese');
+-- the search documents stay the same
+ALTER TABLE books DISABLE TRIGGER book_search_update;
+ALTER TABLE books ADD COLUMN language_enum lang;
+-- fails on books in languages that the enum does not have
+UPDATE books SET language_enum = CASE language
+    WHEN 'en' THEN 'english'::lang
+    WHEN 'ru' THEN 'russian'::lang
+    WHEN 'uk' THEN 'ukrainian'::lang
+    WHEN 'de' THEN 'german'::lang
+    WHEN 'zh' THEN 'chinese'::lang
+    WHEN 'ja' THEN 'japanese'::lang
+END;
+ALTER TABLE books DROP COLUMN language;
+ALTER TABLE books RENAME COLUMN language_enum TO language;
+ALTER TABLE books ALTER COLUMN language SET NOT NULL;
+ALTER TABLE books ENABLE TRIGGER book_search_update;
+DROP TABLE languages;
+
+CREATE FUNCTION lang_search_config(lang) RETURNS regconfig AS $$
+    SELECT CASE $1
+        WHEN 'english' THEN 'english'::regconfig
+        WHEN 'russian' THEN 'russian'::regconfig
+        WHEN 'german' THEN 'german'::regconfig
+        ELSE 'simple'::regconfig
+    END
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE FUNCTION book_search_document(books) RETURNS tsvector AS $$
+    SELECT setweight(to_tsvector(lang_search_config($1.language), $1.title), 'A')
+        || setweight(to_tsvector(lang_search_config($1.language), $1.author), 'B')
+        || setweight(to_tsvector(lang_search_config($1.language), $1.description), 'C')
+$$ LANGUAGE sql IMMUTABLE;
@@ -0,0 +1,100 @@
+CREATE TABLE languages (
+    -- ISO 639-1
+    code varchar(3) primary key,
+    -- ISO 639-2/B, used by MARC
+    bibliographic_code char(3) not null unique,
+    -- ISO 639-2/T, used by EPUB and PDF metadata
+    terminology_code char(3) not null unique,
+    english_name text not null,
+    native_name text not null,
+    -- text search configuration used to stem books written in the language;
+    -- existing documents are not re-stemmed when it changes
+    search_config regconfig not null default 'simple'
+);
+INSERT INTO languages (code, bibliographic_code, terminology_code, english_name, native_name) VALUES
+    ('en', 'eng', 'eng', 'English', 'English'),
+    ('ru', 'rus', 'rus', 'Russian', 'Русский'),
+    ('uk', 'ukr', 'ukr', 'Ukrainian', 'Українська'),
+    ('de', 'ger', 'deu', 'German', 'Deutsch'),
+    ('zh', 'chi', 'zho', 'Chinese', '中文'),
+    ('ja', 'jpn', 'jpn', 'Japanese', '日本語'),
+    ('ar', 'ara', 'ara', 'Arabic', 'العربية'),
+    ('be', 'bel', 'bel', 'Belarusian', 'Беларуская'),
+    ('cs', 'cze', 'ces', 'Czech', 'Čeština'),
+    ('da', 'dan', 'dan', 'Danish', 'Dansk'),
+    ('el', 'gre', 'ell', 'Greek', 'Ελληνικά'),
+    ('es', 'spa', 'spa', 'Spanish', 'Español'),
+    ('fi', 'fin', 'fin', 'Finnish', 'Suomi'),
+    ('fr', 'fre', 'fra', 'French', 'Français'),
+    ('he', 'heb', 'heb', 'Hebrew', 'עברית'),
+    ('hi', 'hin', 'hin', 'Hindi', 'हिन्दी'),
+    ('it', 'ita', 'ita', 'Italian', 'Italiano'),
+    ('ko', 'kor', 'kor', 'Korean', '한국어'),
+    ('la', 'lat', 'lat', 'Latin', 'Latina'),
+    ('nl', 'dut', 'nld', 'Dutch', 'Nederlands'),
+    ('no', 'nor', 'nor', 'Norwegian', 'Norsk'),
+    ('pl', 'pol', 'pol', 'Polish', 'Polski'),
+    ('pt', 'por', 'por', 'Portuguese', 'Português'),
+    ('sv', 'swe', 'swe', 'Swedish', 'Svenska'),
+    ('tr', 'tur', 'tur', 'Turkish', 'Türkçe');
+-- Configurations are looked up by name, since not every PostgreSQL version
+-- ships all of them (older ones lack `hindi`); languages without one keep
+-- 'simple'.
+UPDATE languages SET search_config = cfg.oid::regconfig
+FROM (VALUES
+    ('en', 'english'),
+    ('ru', 'russian'),
+    ('de', 'german'),
+    ('ar', 'arabic'),
+    ('da', 'danish'),
+    ('el', 'greek'),
+    ('es', 'spanish'),
+    ('fi', 'finnish'),
+    ('fr', 'french'),
+    ('hi', 'hindi'),
+    ('it', 'italian'),
+    ('nl', 'dutch'),
+    ('no', 'norwegian'),
+    ('pt', 'portuguese'),
+    ('sv', 'swedish'),
+    ('tr', 'turkish')
+) AS wanted (code, config)
+JOIN pg_ts_config AS cfg ON cfg.cfgname = wanted.config
+    AND cfg.cfgnamespace = 'pg_catalog'::regnamespace
+WHERE languages.code = wanted.code;
+
+DROP FUNCTION book_search_document(books);
+DROP FUNCTION lang_search_config(lang);
+
+-- the search documents stay the same
+ALTER TABLE books DISABLE TRIGGER book_search_update;
+ALTER TABLE books ADD COLUMN language_code varchar(3) references languages(code);
+UPDATE books SET language_code = CASE language
+    WHEN 'english' THEN 'en'
+    WHEN 'russian' THEN 'ru'
+    WHEN 'ukrainian' THEN 'uk'
+    WHEN 'german' THEN 'de'
+    WHEN 'chinese' THEN 'zh'
+    WHEN 'japanese' THEN 'ja'
+END;
+ALTER TABLE books DROP COLUMN language;
+ALTER TABLE books RENAME COLUMN language_code TO language;
+ALTER TABLE books ALTER COLUMN language SET NOT NULL;
+ALTER TABLE books ENABLE TRIGGER book_search_update;
+DROP TYPE lang;
+
+CREATE FUNCTION language_search_config(varchar) RETURNS regconfig AS $$
+    SELECT coalesce(
+        (SELECT search_config FROM languages WHERE code = $1),
+        'simple'::regconfig
+    )
+$$ LANGUAGE sql STABLE;
+
+CREATE FUNCTION book_search_document(books) RETURNS tsvector AS $$
+    SELECT setweight(to_tsvector(language_search_config($1.language), $1.title), 'A')
+        || setweight(to_tsvector(language_search_config($1.language), $1.author), 'B')
+        || setweight(to_tsvector(language_search_config($1.language), $1.description), 'C')
+$$ LANGUAGE sql STABLE;
+
+-- ORs the queries parsed with every search configuration in use.
+CREATE AGGREGATE tsquery_or_agg(tsquery) (SFUNC = tsquery_or, STYPE = tsquery);
@@ -0,0 +1,50 @@
+CREATE TYPE contributor_role AS ENUM ('author', 'translator', 'editor', 'illustrator');
+CREATE TABLE authors (
+    id serial primary key,
+    name text not null unique
+);
+CREATE TABLE book_contributors (
+    isbn bigint not null references books(isbn) on delete cascade,
+    author_id int not null references authors(id),
+    role contributor_role not null,
+    primary key (isbn, author_id, role),
+    -- order of the contributors of a book, starting at 0
+    position int not null
+);
+CREATE INDEX book_contributors_author_id_idx ON book_contributors (author_id);
+
+-- Same rules as `db::contributors::split_names`: names are separated by `;`,
+-- `&` or `and`, and by commas unless that would leave a part without a space,
+-- as in the inverted `Rowling, J. K.`. Runs of whitespace count as one space.
+CREATE FUNCTION split_names(byline text) RETURNS TABLE (name text, ordinal int) AS $$
+DECLARE
+    part text;
+    pieces text[];
+BEGIN
+    ordinal := 0;
+    FOREACH part IN ARRAY regexp_split_to_array(
+        btrim(regexp_replace(byline, '\s+', ' ', 'g')), '\s*;\s*|\s+(&|and)\s+'
+    ) LOOP
+        pieces := ARRAY(SELECT btrim(piece) FROM unnest(string_to_array(part, ',')) AS piece);
+        IF EXISTS (SELECT FROM unnest(pieces) AS piece WHERE piece !~ '\s') THEN
+            pieces := ARRAY[btrim(part)];
+        END IF;
+        FOREACH name IN ARRAY pieces LOOP
+            IF name <> '' THEN
+                RETURN NEXT;
+                ordinal := ordinal + 1;
+            END IF;
+        END LOOP;
+    END LOOP;
+END;
+$$ LANGUAGE plpgsql;
+
+INSERT INTO authors (name)
+SELECT DISTINCT names.name FROM books, split_names(books.author) AS names;
+INSERT INTO book_contributors (isbn, author_id, role, position)
+SELECT books.isbn, authors.id, 'author', names.ordinal
+FROM books, split_names(books.author) AS names
+JOIN authors ON authors.name = names.name
+ON CONFLICT DO NOTHING;
+
+DROP FUNCTION split_names(text);
@@ -0,0 +1,4 @@
+DROP TABLE author_names;
+ALTER TABLE authors DROP COLUMN search_key;
+DROP FUNCTION name_key(text);
+DROP TYPE script;
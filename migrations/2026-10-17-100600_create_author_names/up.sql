@@ -0,0 +1,32 @@
+CREATE TYPE script AS ENUM ('latin', 'cyrillic', 'han', 'kana', 'other');
+
+-- Folds a name into a key shared by its spellings and transliterations:
+-- Cyrillic is romanized, diacritics and punctuation are dropped, and the
+-- letters that romanizations disagree on are merged, so that `Tolstoy`,
+-- `Tolstoj` and `Толстой` all become `tolstoi`. Other scripts are kept.
+CREATE FUNCTION name_key(text) RETURNS text AS $$
+    SELECT btrim(regexp_replace(regexp_replace(regexp_replace(
+        replace(replace(replace(translate(
+            replace(replace(replace(replace(replace(replace(
+            replace(replace(replace(replace(replace(
+                lower($1),
+                'щ', 'shch'), 'ж', 'zh'), 'х', 'kh'), 'ц', 'ts'), 'ч', 'ch'), 'ш', 'sh'),
+                'ю', 'yu'), 'я', 'ya'), 'ё', 'yo'), 'є', 'ye'), 'ї', 'yi'),
+            'абвгґдеэзийіклмнопрстуфыáàâäãåāçćčďéèêëēěíìîïīłńñňóòôöõøōřśšťúùûüūůýÿźżžjywъь''’ʼ',
+            'abvggdeeziiiklmnoprstufiaaaaaaacccdeeeeeeiiiiilnnnooooooorsstuuuuuuiizzziiv'),
+            'x', 'ks'), 'tch', 'ch'), 'ie', 'e'),
+        '[[:punct:]]', '', 'g'),
+        '(.)\1+', '\1', 'g'),
+        '\s+', ' ', 'g'))
+$$ LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE;
+
+ALTER TABLE authors ADD COLUMN search_key text not null GENERATED ALWAYS AS (name_key(name)) STORED;
+
+-- Alternate names of an author, whose preferred name is `authors.name`.
+CREATE TABLE author_names (
+    name text primary key,
+    author_id int not null references authors(id) on delete cascade,
+    script script not null,
+    search_key text not null GENERATED ALWAYS AS (name_key(name)) STORED
+);
+CREATE INDEX author_names_author_id_idx ON author_names (author_id);
@@ -0,0 +1,9 @@
+-- A work groups the editions and translations of the same text; books that
+-- are the only edition the catalog holds need not belong to one.
+CREATE TABLE works (
+    id serial primary key,
+    title text not null,
+    original_language varchar(3) not null references languages(code)
+);
+ALTER TABLE books ADD COLUMN work_id int references works(id) on delete set null;
+CREATE INDEX books_work_id_idx ON books (work_id);
@@ -0,0 +1,2 @@
+DROP TABLE book_series;
+DROP TABLE series;
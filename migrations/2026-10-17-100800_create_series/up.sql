@@ -0,0 +1,12 @@
+CREATE TABLE series (
+    id serial primary key,
+    title text not null unique
+);
+CREATE TABLE book_series (
+    isbn bigint not null references books(isbn) on delete cascade,
+    series_id int not null references series(id) on delete cascade,
+    -- reading order, starting at 1; editions of the same volume share it
+    volume int not null check (volume > 0),
+    primary key (isbn, series_id)
+);
+CREATE INDEX book_series_series_id_idx ON book_series (series_id, volume);
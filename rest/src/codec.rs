@@ -0,0 +1,147 @@
+use crate::error::ApiError;
+use actix_web::{
+    body::BoxBody,
+    dev::{Payload, ServiceResponse},
+    http::{
+        header::{Accept, Header, CONTENT_TYPE},
+        StatusCode,
+    },
+    FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use serde::{Deserialize, Serialize};
+use speedy::{LittleEndian, Readable, Writable};
+use std::future::{ready, Ready};
+
+const JSON: &str = "application/json";
+const SPEEDY: &str = "application/octet-stream";
+
+/// Wire format of a request or response body.
+///
+/// Speedy is the compact default used by our Rust clients, JSON is chosen
+/// when the client asks for it through `Content-Type` or `Accept`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Speedy,
+    Json,
+}
+
+impl Format {
+    fn from_mime(essence: &str) -> Option<Self> {
+        if essence == JSON || essence.ends_with("+json") {
+            Some(Self::Json)
+        } else if essence == SPEEDY || essence == "*/*" || essence == "application/*" {
+            Some(Self::Speedy)
+        } else {
+            None
+        }
+    }
+
+    pub fn of_content(req: &HttpRequest) -> Self {
+        Self::from_mime(req.content_type()).unwrap_or(Self::Speedy)
+    }
+
+    pub fn accepted(req: &HttpRequest) -> Self {
+        Accept::parse(req)
+            .ok()
+            .and_then(|accept| {
+                accept
+                    .ranked()
+                    .iter()
+                    .find_map(|mime| Self::from_mime(mime.essence_str()))
+            })
+            .unwrap_or(Self::Speedy)
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Self::Speedy => SPEEDY,
+            Self::Json => JSON,
+        }
+    }
+
+    pub fn decode<'a, T>(self, body: &'a [u8]) -> Result<T, ApiError>
+    where
+        T: Readable<'a, LittleEndian> + Deserialize<'a>,
+    {
+        match self {
+            Self::Speedy => Ok(T::read_from_buffer(body)?),
+            Self::Json => {
+                serde_json::from_slice(body).map_err(|e| ApiError::BadRequest(e.to_string()))
+            }
+        }
+    }
+
+    pub fn encode<T>(self, value: &T) -> Result<Vec<u8>, ApiError>
+    where
+        T: Writable<LittleEndian> + Serialize,
+    {
+        match self {
+            Self::Speedy => value.write_to_vec().map_err(|e| e.to_string()),
+            Self::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+        }
+        .map_err(ApiError::Internal)
+    }
+
+    pub fn respond<T>(self, status: StatusCode, value: &T) -> Result<HttpResponse, ApiError>
+    where
+        T: Writable<LittleEndian> + Serialize,
+    {
+        Ok(HttpResponse::build(status)
+            .insert_header((CONTENT_TYPE, self.mime()))
+            .body(self.encode(value)?))
+    }
+}
+
+/// Formats negotiated for a single request.
+pub struct Codec {
+    pub content: Format,
+    pub accept: Format,
+}
+
+impl Codec {
+    pub fn decode<'a, T>(&self, body: &'a [u8]) -> Result<T, ApiError>
+    where
+        T: Readable<'a, LittleEndian> + Deserialize<'a>,
+    {
+        self.content.decode(body)
+    }
+
+    pub fn respond<T>(&self, value: &T) -> Result<HttpResponse, ApiError>
+    where
+        T: Writable<LittleEndian> + Serialize,
+    {
+        self.accept.respond(StatusCode::OK, value)
+    }
+}
+
+impl FromRequest for Codec {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Self {
+            content: Format::of_content(req),
+            accept: Format::accepted(req),
+        }))
+    }
+}
+
+/// Re-encodes an [`ApiError`] body in the format the client accepts.
+pub fn negotiate_error(res: ServiceResponse<BoxBody>) -> ServiceResponse<BoxBody> {
+    let accept = Format::accepted(res.request());
+    if accept == Format::Speedy {
+        return res;
+    }
+    let resp = match res
+        .response()
+        .error()
+        .and_then(|e| e.as_error::<ApiError>())
+    {
+        Some(e) => accept.respond(e.status_code(), &e.body()),
+        None => return res,
+    };
+    match resp {
+        Ok(resp) => res.into_response(resp),
+        Err(_) => res,
+    }
+}
@@ -0,0 +1,87 @@
+use crate::codec::Format;
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use db::error::{ErrorBody, ErrorKind};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ApiError {
+    Db(db::Error),
+    BadRequest(String),
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Db(e) => e.kind(),
+            Self::BadRequest(_) => ErrorKind::BadRequest,
+            Self::Internal(_) => ErrorKind::Internal,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            kind: self.kind(),
+            message: self.to_string(),
+            fields: match self {
+                Self::Db(db::Error::Validation(errors)) => errors.clone(),
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Db(e) => e.fmt(f),
+            Self::BadRequest(msg) => write!(f, "bad request: {msg}"),
+            Self::Internal(msg) => write!(f, "internal error: {msg}"),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self.kind() {
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::HasReviews | ErrorKind::UniqueViolation => StatusCode::CONFLICT,
+            ErrorKind::ForeignKeyViolation | ErrorKind::Validation => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ErrorKind::Connection => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        Format::Speedy
+            .respond(self.status_code(), &self.body())
+            .unwrap_or_else(|_| HttpResponse::build(self.status_code()).finish())
+    }
+}
+
+impl From<db::Error> for ApiError {
+    fn from(e: db::Error) -> Self {
+        Self::Db(e)
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for ApiError {
+    fn from(e: diesel::r2d2::PoolError) -> Self {
+        Self::Db(e.into())
+    }
+}
+
+impl From<speedy::Error> for ApiError {
+    fn from(e: speedy::Error) -> Self {
+        Self::BadRequest(e.to_string())
+    }
+}
+
+impl From<actix_multipart::MultipartError> for ApiError {
+    fn from(e: actix_multipart::MultipartError) -> Self {
+        Self::BadRequest(e.to_string())
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{error::ApiError, stream};
+use actix_multipart::Multipart;
+use actix_web::{
+    body::SizedStream,
+    http::header::{
+        Charset, ContentDisposition, ContentRange, ContentRangeSpec, DispositionParam,
+        DispositionType, ETag, EntityTag, ExtendedValue, Header, IfNoneMatch, IfRange, Range,
+        ACCEPT_RANGES,
+    },
+    HttpRequest, HttpResponse,
+};
+use db::{
+    models::{Book, BookFile, FileKind, ThumbnailSize},
+    storage::Storage,
+};
+use futures_util::TryStreamExt;
+
+/// Largest accepted upload, in bytes.
+pub const MAX_UPLOAD: usize = 256 << 20;
+
+/// Reads the `file` field of a `multipart/form-data` upload.
+pub async fn read_upload(mut payload: Multipart) -> Result<Vec<u8>, ApiError> {
+    while let Some(mut field) = payload.try_next().await? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let mut data = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            if data.len() + chunk.len() > MAX_UPLOAD {
+                return Err(ApiError::BadRequest(format!(
+                    "file is larger than {MAX_UPLOAD} bytes"
+                )));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        return Ok(data);
+    }
+    Err(ApiError::BadRequest("missing `file` field".into()))
+}
+
+/// Responds with the whole file or the single byte range asked for, honoring
+/// `If-None-Match` and `If-Range` against the file's SHA-256. The body is
+/// streamed from storage as it is read.
+pub fn respond(
+    req: &HttpRequest,
+    storage: &Storage,
+    book: &Book,
+    file: &BookFile,
+) -> Result<HttpResponse, ApiError> {
+    let etag = EntityTag::new_strong(file.sha256.clone());
+    if not_modified(req, &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    let size = file.size as u64;
+    // a stale `If-Range` means the client's partial copy is useless
+    let range_applies = match IfRange::parse(req) {
+        Ok(IfRange::EntityTag(tag)) => tag.strong_eq(&etag),
+        Ok(IfRange::Date(_)) => false,
+        Err(_) => true,
+    };
+    // several ranges would need a multipart response, the whole file will do
+    let range = match Range::parse(req) {
+        Ok(Range::Bytes(specs)) if range_applies && specs.len() == 1 => {
+            Some(specs[0].to_satisfiable_range(size))
+        }
+        _ => None,
+    };
+
+    let mut builder = match range {
+        None => HttpResponse::Ok(),
+        Some(None) => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(size),
+                }))
+                .finish());
+        }
+        Some(Some((first, last))) => {
+            let mut builder = HttpResponse::PartialContent();
+            builder.insert_header(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((first, last)),
+                instance_length: Some(size),
+            }));
+            builder
+        }
+    };
+    let (start, len) = match range {
+        Some(Some((first, last))) => (first, last - first + 1),
+        _ => (0, size),
+    };
+    let body = stream::from_reader(storage.open_range(file, start, len)?);
+    Ok(builder
+        .insert_header(ETag(etag))
+        .insert_header((ACCEPT_RANGES, "bytes"))
+        .insert_header(disposition(book, file.kind, extension(&file.mime)))
+        .content_type(file.mime.as_str())
+        .body(SizedStream::new(len, body)))
+}
+
+/// Responds with a thumbnail of the cover, honoring `If-None-Match`.
+pub fn respond_thumbnail(
+    req: &HttpRequest,
+    storage: &Storage,
+    book: &Book,
+    cover: &BookFile,
+    size: ThumbnailSize,
+) -> Result<HttpResponse, ApiError> {
+    let etag = EntityTag::new_strong(format!("{}-{}", cover.sha256, size.pixels()));
+    if not_modified(req, &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .insert_header(disposition(book, FileKind::Cover, "jpg"))
+        .content_type("image/jpeg")
+        .body(storage.thumbnail(cover, size)?))
+}
+
+fn not_modified(req: &HttpRequest, etag: &EntityTag) -> bool {
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        Err(_) => false,
+    }
+}
+
+/// Covers are shown inline, book files are downloaded as
+/// `Author - Title.ext`.
+fn disposition(book: &Book, kind: FileKind, extension: &str) -> ContentDisposition {
+    let (disposition, name) = match kind {
+        FileKind::Cover => (
+            DispositionType::Inline,
+            format!("{} - {} (cover)", book.author, book.title),
+        ),
+        FileKind::Book => (
+            DispositionType::Attachment,
+            format!("{} - {}", book.author, book.title),
+        ),
+    };
+    let name = format!("{}.{extension}", sanitize(&name));
+    let ascii = name
+        .chars()
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+    let mut parameters = vec![DispositionParam::Filename(ascii)];
+    if !name.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".into()),
+            language_tag: None,
+            value: name.into_bytes(),
+        }));
+    }
+    ContentDisposition {
+        disposition,
+        parameters,
+    }
+}
+
+/// Drops characters that are not allowed in file names on common systems.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .filter(|c| {
+            !c.is_control() && !matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|')
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn extension(mime: &str) -> &'static str {
+    match mime {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "application/pdf" => "pdf",
+        "application/epub+zip" => "epub",
+        "application/zip" => "zip",
+        _ => "bin",
+    }
+}
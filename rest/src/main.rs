@@ -1,90 +1,807 @@
+use actix_multipart::Multipart;
 use actix_web::{
-    delete, get, post, put,
+    delete,
+    dev::Service,
+    get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    patch, post, put,
     web::{self, Bytes},
-    App, HttpResponse, HttpServer,
+    App, HttpRequest, HttpResponse, HttpServer,
+};
+use codec::Codec;
+use db::{
+    export::{self, ExportFormat, ExportOptions},
+    filter::BookFilter,
+    models::{
+        BookChanges, ContributorRole, DeletePolicy, FileKind, Isbn, NewAuthorName, NewBook,
+        NewContributor, NewReview, NewReviewPart, NewSeries, NewSeriesEntry, NewWork,
+        ThumbnailSize,
+    },
+    pagination::{self, BookSortKey, PageRequest, ReviewSortKey, Sort},
+    storage::{self, Storage},
 };
-use db::models::{NewReview, NewReviewPart};
 use diesel::{r2d2::ConnectionManager, PgConnection};
 use dotenvy::dotenv;
+use error::ApiError;
 use r2d2::Pool;
-use speedy::{Readable, Writable};
-use std::{env, io, time::SystemTime};
+use serde::Deserialize;
+use std::{env, io, str::FromStr, time::SystemTime};
+
+mod codec;
+mod error;
+mod files;
+mod stream;
 
 type DbPool = Pool<ConnectionManager<PgConnection>>;
 
+#[derive(Deserialize)]
+struct ListQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<String>,
+}
+
+impl ListQuery {
+    fn page<K>(&self) -> Result<PageRequest<'_, K>, ApiError>
+    where
+        K: FromStr<Err = db::Error>,
+        Sort<K>: Default,
+    {
+        Ok(PageRequest {
+            sort: match &self.sort {
+                Some(sort) => sort.parse()?,
+                None => Default::default(),
+            },
+            limit: self.limit.unwrap_or(pagination::DEFAULT_LIMIT),
+            cursor: self.cursor.as_deref(),
+        })
+    }
+}
+
+fn ensure_found(affected: usize) -> Result<HttpResponse, ApiError> {
+    if affected == 0 {
+        Err(db::Error::NotFound.into())
+    } else {
+        Ok(HttpResponse::Ok().into())
+    }
+}
+
+#[post("/books")]
+async fn post_book(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let book = codec.decode::<NewBook>(&body)?;
+    db::create_book(&mut conn, &book)?;
+    Ok(HttpResponse::Ok().into())
+}
+
+#[get("/books")]
+async fn get_books(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    query: web::Query<ListQuery>,
+    filter: web::Query<BookFilter>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let books = db::list_books(&mut conn, &filter, &query.page::<BookSortKey>()?)?;
+    codec.respond(&books)
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    limit: Option<i64>,
+}
+
+#[get("/books/search")]
+async fn search_books(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    query: web::Query<SearchQuery>,
+    filter: web::Query<BookFilter>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let limit = query.limit.unwrap_or(pagination::DEFAULT_LIMIT);
+    let hits = db::search_books(&mut conn, &query.q, &filter, limit)?;
+    codec.respond(&hits)
+}
+
+/// The languages books can be written in, for filling a dropdown.
+#[get("/languages")]
+async fn get_languages(pool: web::Data<DbPool>, codec: Codec) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    codec.respond(&db::load_languages(&mut conn)?)
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    format: ExportFormat,
+    #[serde(default)]
+    reviews: bool,
+}
+
+/// The books selected by the filter as a `catalog.{csv,jsonl,mrc,xml}`
+/// download, streamed as it is exported.
+#[get("/export")]
+async fn export_catalog(
+    pool: web::Data<DbPool>,
+    query: web::Query<ExportQuery>,
+    filter: web::Query<BookFilter>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let options = ExportOptions {
+        filter: filter.into_inner(),
+        reviews: query.reviews,
+    };
+    let format = query.format;
+    let body = stream::from_writer(move |out| {
+        export::export(&mut conn, format, &options, out)?;
+        Ok(())
+    });
+    Ok(HttpResponse::Ok()
+        .content_type(query.format.mime())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "catalog.{}",
+                query.format.extension()
+            ))],
+        })
+        .streaming(body))
+}
+
+#[get("/books/{isbn}")]
+async fn get_book(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    let book = db::get_book(&mut conn, isbn)?;
+    codec.respond(&book)
+}
+
+#[put("/books")]
+async fn update_book(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let book = codec.decode::<NewBook>(&body)?;
+    let book = db::update_book(&mut conn, book.isbn, &book.into())?;
+    codec.respond(&book)
+}
+
+#[patch("/books/{isbn}")]
+async fn patch_book(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    let changes = codec.decode::<BookChanges>(&body)?;
+    let book = db::update_book(&mut conn, isbn, &changes)?;
+    codec.respond(&book)
+}
+
+#[get("/books/{isbn}/contributors")]
+async fn get_contributors(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    let contributors = db::get_contributors(&mut conn, isbn)?;
+    codec.respond(&contributors)
+}
+
+/// Replaces the whole list and responds with it as stored.
+#[put("/books/{isbn}/contributors")]
+async fn put_contributors(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    let contributors = codec.decode::<Vec<NewContributor>>(&body)?;
+    let contributors = db::set_contributors(&mut conn, isbn, &contributors)?;
+    codec.respond(&contributors)
+}
+
+/// Puts the book in a work, given as its id, or takes it out with `null`.
+#[put("/books/{isbn}/work")]
+async fn put_book_work(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    let work_id = codec.decode::<Option<i32>>(&body)?;
+    let book = db::set_work(&mut conn, isbn, work_id)?;
+    codec.respond(&book)
+}
+
+/// The other editions of the book's work, for "also available in".
+#[get("/books/{isbn}/editions")]
+async fn get_other_editions(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    let books = db::other_editions(&mut conn, isbn)?;
+    codec.respond(&books)
+}
+
+#[post("/works")]
+async fn post_work(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let work = codec.decode::<NewWork>(&body)?;
+    let work = db::create_work(&mut conn, &work)?;
+    codec.respond(&work)
+}
+
+#[get("/works/{id}")]
+async fn get_work(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let work = db::get_work(&mut conn, id.into_inner())?;
+    codec.respond(&work)
+}
+
+#[put("/works/{id}")]
+async fn update_work(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let work = codec.decode::<NewWork>(&body)?;
+    let work = db::update_work(&mut conn, id.into_inner(), &work)?;
+    codec.respond(&work)
+}
+
+#[delete("/works/{id}")]
+async fn delete_work(
+    pool: web::Data<DbPool>,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    ensure_found(db::delete_work(&mut conn, id.into_inner())?)
+}
+
+#[get("/works/{id}/editions")]
+async fn get_editions(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let books = db::get_editions(&mut conn, id.into_inner())?;
+    codec.respond(&books)
+}
+
+#[get("/books/{isbn}/series")]
+async fn get_book_series(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    let entries = db::get_book_series(&mut conn, isbn)?;
+    codec.respond(&entries)
+}
+
+/// Replaces the whole list and responds with it as stored.
+#[put("/books/{isbn}/series")]
+async fn put_book_series(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    let entries = codec.decode::<Vec<NewSeriesEntry>>(&body)?;
+    let entries = db::set_book_series(&mut conn, isbn, &entries)?;
+    codec.respond(&entries)
+}
+
+#[post("/series")]
+async fn post_series(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let series = codec.decode::<NewSeries>(&body)?;
+    let series = db::create_series(&mut conn, &series)?;
+    codec.respond(&series)
+}
+
+#[get("/series")]
+async fn get_all_series(pool: web::Data<DbPool>, codec: Codec) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let series = db::load_series(&mut conn)?;
+    codec.respond(&series)
+}
+
+#[get("/series/{id}")]
+async fn get_series(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let series = db::get_series(&mut conn, id.into_inner())?;
+    codec.respond(&series)
+}
+
+#[put("/series/{id}")]
+async fn rename_series(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let series = codec.decode::<NewSeries>(&body)?;
+    let series = db::rename_series(&mut conn, id.into_inner(), &series)?;
+    codec.respond(&series)
+}
+
+#[delete("/series/{id}")]
+async fn delete_series(
+    pool: web::Data<DbPool>,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    ensure_found(db::delete_series(&mut conn, id.into_inner())?)
+}
+
+/// The books of the series in reading order, with their volume numbers.
+#[get("/series/{id}/books")]
+async fn get_volumes(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let volumes = db::get_volumes(&mut conn, id.into_inner())?;
+    codec.respond(&volumes)
+}
+
+#[derive(Deserialize)]
+struct AuthorQuery {
+    #[serde(default)]
+    q: String,
+    limit: Option<i64>,
+}
+
+#[get("/authors")]
+async fn find_authors(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    query: web::Query<AuthorQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let limit = query.limit.unwrap_or(pagination::DEFAULT_LIMIT);
+    let authors = db::find_authors(&mut conn, &query.q, limit)?;
+    codec.respond(&authors)
+}
+
+#[get("/authors/{id}")]
+async fn get_author(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let author = db::get_author(&mut conn, id.into_inner())?;
+    codec.respond(&author)
+}
+
+#[delete("/authors/{id}")]
+async fn delete_author(
+    pool: web::Data<DbPool>,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    ensure_found(db::delete_author(&mut conn, id.into_inner())?)
+}
+
+/// Sets the preferred name, given as a string.
+#[put("/authors/{id}/name")]
+async fn put_author_name(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let name = codec.decode::<String>(&body)?;
+    let author = db::set_preferred_name(&mut conn, id.into_inner(), &name)?;
+    codec.respond(&author)
+}
+
+#[get("/authors/{id}/names")]
+async fn get_author_names(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let names = db::get_author_names(&mut conn, id.into_inner())?;
+    codec.respond(&names)
+}
+
+#[post("/authors/{id}/names")]
+async fn post_author_name(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let name = codec.decode::<NewAuthorName>(&body)?;
+    let name = db::add_author_name(&mut conn, id.into_inner(), &name)?;
+    codec.respond(&name)
+}
+
+#[delete("/authors/{id}/names/{name}")]
+async fn delete_author_name(
+    pool: web::Data<DbPool>,
+    path: web::Path<(i32, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, name) = path.into_inner();
+    let mut conn = pool.get()?;
+    ensure_found(db::remove_author_name(&mut conn, id, &name)?)
+}
+
+/// Merges the author `from` into `id`, responding with the merged author.
+#[post("/authors/{id}/merge/{from}")]
+async fn merge_authors(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    path: web::Path<(i32, i32)>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, from) = path.into_inner();
+    let mut conn = pool.get()?;
+    let author = db::merge_authors(&mut conn, id, from)?;
+    codec.respond(&author)
+}
+
+#[derive(Deserialize)]
+struct RoleQuery {
+    role: Option<ContributorRole>,
+}
+
+#[get("/authors/{id}/books")]
+async fn get_books_by_contributor(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    id: web::Path<i32>,
+    query: web::Query<RoleQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let books = db::books_by_contributor(&mut conn, id.into_inner(), query.role)?;
+    codec.respond(&books)
+}
+
+#[derive(Deserialize)]
+struct DeleteQuery {
+    #[serde(default)]
+    policy: DeletePolicy,
+}
+
+#[delete("/books/{isbn}")]
+async fn delete_book(
+    pool: web::Data<DbPool>,
+    storage: web::Data<Storage>,
+    isbn: web::Path<Isbn>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    storage.delete_book(&mut conn, isbn, query.policy)?;
+    Ok(HttpResponse::Ok().into())
+}
+
+#[post("/books/{isbn}/archive")]
+async fn archive_book(
+    pool: web::Data<DbPool>,
+    isbn: web::Path<Isbn>,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    ensure_found(db::archive_book(&mut conn, isbn)?)
+}
+
+#[post("/books/{isbn}/restore")]
+async fn restore_book(
+    pool: web::Data<DbPool>,
+    isbn: web::Path<Isbn>,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let mut conn = pool.get()?;
+    ensure_found(db::restore_book(&mut conn, isbn)?)
+}
+
+async fn upload_file(
+    pool: web::Data<DbPool>,
+    storage: web::Data<Storage>,
+    codec: Codec,
+    isbn: Isbn,
+    kind: FileKind,
+    payload: Multipart,
+) -> Result<HttpResponse, ApiError> {
+    let data = files::read_upload(payload).await?;
+    if kind == FileKind::Cover && !storage::sniff_mime(&data).starts_with("image/") {
+        return Err(ApiError::BadRequest(
+            "cover must be a PNG, JPEG, GIF or WebP image".into(),
+        ));
+    }
+    let mut conn = pool.get()?;
+    let mut files = storage.transaction();
+    files.stage(kind, isbn, &mut data.as_slice())?;
+    files.run(&mut conn, |conn| db::get_book(conn, isbn).map(drop))?;
+    codec.respond(&storage.file(&mut conn, kind, isbn)?)
+}
+
+fn download_file(
+    req: &HttpRequest,
+    pool: &DbPool,
+    storage: &Storage,
+    isbn: Isbn,
+    kind: FileKind,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
+    let book = db::get_book(&mut conn, isbn)?;
+    let file = storage.file(&mut conn, kind, isbn)?;
+    files::respond(req, storage, &book, &file)
+}
+
+#[post("/books/{isbn}/cover")]
+async fn upload_cover(
+    pool: web::Data<DbPool>,
+    storage: web::Data<Storage>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+    payload: Multipart,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    upload_file(pool, storage, codec, isbn, FileKind::Cover, payload).await
+}
+
+#[derive(Deserialize)]
+struct CoverQuery {
+    size: Option<ThumbnailSize>,
+}
+
+/// The full-size cover, or a thumbnail with `?size=small|medium|large`.
+#[get("/books/{isbn}/cover")]
+async fn download_cover(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    storage: web::Data<Storage>,
+    isbn: web::Path<Isbn>,
+    query: web::Query<CoverQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    let Some(size) = query.size else {
+        return download_file(&req, &pool, &storage, isbn, FileKind::Cover);
+    };
+    let mut conn = pool.get()?;
+    let book = db::get_book(&mut conn, isbn)?;
+    let cover = storage.file(&mut conn, FileKind::Cover, isbn)?;
+    files::respond_thumbnail(&req, &storage, &book, &cover, size)
+}
+
+#[post("/books/{isbn}/file")]
+async fn upload_book_file(
+    pool: web::Data<DbPool>,
+    storage: web::Data<Storage>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+    payload: Multipart,
+) -> Result<HttpResponse, ApiError> {
+    let isbn = isbn.into_inner();
+    upload_file(pool, storage, codec, isbn, FileKind::Book, payload).await
+}
+
+#[get("/books/{isbn}/file")]
+async fn download_book_file(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    storage: web::Data<Storage>,
+    isbn: web::Path<Isbn>,
+) -> Result<HttpResponse, ApiError> {
+    download_file(&req, &pool, &storage, isbn.into_inner(), FileKind::Book)
+}
+
 #[post("/reviews")]
-async fn post_review(pool: web::Data<DbPool>, body: Bytes) -> HttpResponse {
-    let mut conn = pool.get().unwrap();
+async fn post_review(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
     let created_at = SystemTime::now();
     let NewReviewPart {
         isbn,
         username,
         rating,
         description,
-    } = NewReviewPart::read_from_buffer(&body).unwrap();
+    } = codec.decode(&body)?;
     let review = NewReview {
         isbn,
-        username,
+        username: &username,
         rating,
-        description,
+        description: &description,
         created_at,
         updated_at: created_at,
     };
-    db::create_review(&mut conn, &review).unwrap();
-    HttpResponse::Ok().into()
+    db::create_review(&mut conn, &review)?;
+    Ok(HttpResponse::Ok().into())
+}
+
+#[derive(Deserialize)]
+struct EditionsQuery {
+    /// Include the reviews of the other editions of the book's work.
+    #[serde(default)]
+    editions: bool,
 }
 
 #[get("/reviews/book/{isbn}")]
-async fn get_reviews_by_book(pool: web::Data<DbPool>, isbn: web::Path<i64>) -> Vec<u8> {
+async fn get_reviews_by_book(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    isbn: web::Path<Isbn>,
+    query: web::Query<ListQuery>,
+    editions: web::Query<EditionsQuery>,
+) -> Result<HttpResponse, ApiError> {
     let isbn = isbn.into_inner();
-    let mut conn = pool.get().unwrap();
-    let reviews = db::get_reviews_by_book(&mut conn, isbn).unwrap();
-    reviews.write_to_vec().unwrap()
+    let mut conn = pool.get()?;
+    let page = query.page::<ReviewSortKey>()?;
+    let reviews = db::list_reviews_by_book(&mut conn, isbn, editions.editions, &page)?;
+    codec.respond(&reviews)
 }
 
 #[get("/reviews/user/{username}")]
-async fn get_reviews_by_username(pool: web::Data<DbPool>, username: web::Path<String>) -> Vec<u8> {
+async fn get_reviews_by_username(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    username: web::Path<String>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ApiError> {
     let username = username.into_inner();
-    let mut conn = pool.get().unwrap();
-    let reviews = db::get_reviews_by_username(&mut conn, &username).unwrap();
-    reviews.write_to_vec().unwrap()
+    let mut conn = pool.get()?;
+    let page = query.page::<ReviewSortKey>()?;
+    let reviews = db::list_reviews_by_username(&mut conn, &username, &page)?;
+    codec.respond(&reviews)
 }
 
 #[put("/reviews")]
-async fn update_review(pool: web::Data<DbPool>, body: Bytes) -> HttpResponse {
-    let mut conn = pool.get().unwrap();
+async fn update_review(
+    pool: web::Data<DbPool>,
+    codec: Codec,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
     let updated_at = SystemTime::now();
     let NewReviewPart {
         isbn,
         username,
         rating,
         description,
-    } = NewReviewPart::read_from_buffer(&body).unwrap();
-    db::update_review(&mut conn, isbn, username, description, rating, updated_at).unwrap();
-    HttpResponse::Ok().into()
+    } = codec.decode(&body)?;
+    ensure_found(db::update_review(
+        &mut conn,
+        isbn,
+        &username,
+        &description,
+        rating,
+        updated_at,
+    )?)
 }
 
 #[delete("/reviews/{isbn}/{username}")]
-async fn delete_review<'a>(
+async fn delete_review(
     pool: web::Data<DbPool>,
-    path: web::Path<(i64, String)>,
-) -> HttpResponse {
-    let mut conn = pool.get().unwrap();
+    path: web::Path<(Isbn, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let mut conn = pool.get()?;
     let (isbn, username) = path.into_inner();
-    db::delete_review(&mut conn, isbn, &username).unwrap();
-    HttpResponse::Ok().into()
+    ensure_found(db::delete_review(&mut conn, isbn, &username)?)
 }
 
 fn config(cfg: &mut web::ServiceConfig) {
     dotenv().ok();
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let pool = DbPool::new(ConnectionManager::new(db_url)).expect("Failed to create db pool");
+    let storage = Storage::new(db::establish_blob_store());
     cfg.app_data(web::Data::new(pool.clone()))
-        .service(post_review)
-        .service(get_reviews_by_book)
-        .service(get_reviews_by_username)
-        .service(update_review)
-        .service(delete_review);
+        .app_data(web::Data::new(storage))
+        // an invalid ISBN in the path is a bad request rather than a missing page
+        .app_data(
+            web::PathConfig::default()
+                .error_handler(|e, _| ApiError::BadRequest(e.to_string()).into()),
+        )
+        .service(
+            web::scope("")
+                .wrap_fn(|req, srv| {
+                    let res = srv.call(req);
+                    async { res.await.map(codec::negotiate_error) }
+                })
+                .service(post_book)
+                .service(get_books)
+                .service(search_books)
+                .service(export_catalog)
+                .service(get_languages)
+                .service(get_book)
+                .service(update_book)
+                .service(patch_book)
+                .service(get_contributors)
+                .service(put_contributors)
+                .service(put_book_work)
+                .service(get_other_editions)
+                .service(post_work)
+                .service(get_work)
+                .service(update_work)
+                .service(delete_work)
+                .service(get_editions)
+                .service(get_book_series)
+                .service(put_book_series)
+                .service(post_series)
+                .service(get_all_series)
+                .service(get_series)
+                .service(rename_series)
+                .service(delete_series)
+                .service(get_volumes)
+                .service(find_authors)
+                .service(get_author)
+                .service(delete_author)
+                .service(put_author_name)
+                .service(get_author_names)
+                .service(post_author_name)
+                .service(delete_author_name)
+                .service(merge_authors)
+                .service(get_books_by_contributor)
+                .service(delete_book)
+                .service(archive_book)
+                .service(restore_book)
+                .service(upload_cover)
+                .service(download_cover)
+                .service(upload_book_file)
+                .service(download_book_file)
+                .service(post_review)
+                .service(get_reviews_by_book)
+                .service(get_reviews_by_username)
+                .service(update_review)
+                .service(delete_review),
+        );
 }
 
 #[actix_web::main]
@@ -98,27 +815,45 @@ async fn main() -> io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use actix_multipart::test::create_form_data_payload_and_headers;
     use actix_web::{
+        http::{header, StatusCode},
         test::{self, call_and_read_body, TestRequest},
         App,
     };
-    use db::models::{Rating, Review};
-    use std::time::Duration;
+    use db::{
+        blob::LocalStore,
+        error::{ErrorBody, ErrorKind},
+        models::{
+            Author, AuthorName, Book, BookFile, Contributor, Isbn, Language, Rating, Review,
+            Script, SeriesEntry, Volume, Work,
+        },
+        pagination::Page,
+        search::SearchHit,
+        validation::Field,
+    };
+    use diesel::RunQueryDsl;
+    use image::{DynamicImage, ImageOutputFormat};
+    use speedy::{Readable, Writable};
+    use std::{io::Cursor, time::Duration};
 
     #[actix_web::test]
+    // kept as first written, although `send_request` only needs `&S`
+    #[allow(clippy::unnecessary_mut_passed)]
     async fn api_test() {
         let mut app = test::init_service(App::new().configure(config)).await;
         let (isbn, username, rating, description) =
             (9_780_747_542_155, "anon", Rating::One, "really good book");
+        let isbn = Isbn::new(isbn).unwrap();
 
         let resp = TestRequest::post()
             .uri("/reviews")
             .set_payload(
                 NewReviewPart {
                     isbn,
-                    username,
+                    username: username.into(),
                     rating,
-                    description,
+                    description: description.into(),
                 }
                 .write_to_vec()
                 .unwrap(),
@@ -131,7 +866,7 @@ mod tests {
             .uri(&format!("/reviews/book/{isbn}"))
             .to_request();
         let resp = call_and_read_body(&app, req).await;
-        let reviews = Vec::<Review>::read_from_buffer(&resp).unwrap();
+        let reviews = Page::<Review>::read_from_buffer(&resp).unwrap().items;
         assert_eq!(reviews.len(), 1);
         assert_eq!(reviews[0].isbn, isbn);
         assert_eq!(reviews[0].username, username);
@@ -147,9 +882,9 @@ mod tests {
             .set_payload(
                 NewReviewPart {
                     isbn,
-                    username,
+                    username: username.into(),
                     rating,
-                    description,
+                    description: description.into(),
                 }
                 .write_to_vec()
                 .unwrap(),
@@ -162,7 +897,7 @@ mod tests {
             .uri(&format!("/reviews/user/{username}"))
             .to_request();
         let resp = call_and_read_body(&app, req).await;
-        let reviews = Vec::<Review>::read_from_buffer(&resp).unwrap();
+        let reviews = Page::<Review>::read_from_buffer(&resp).unwrap().items;
         assert_eq!(reviews.len(), 1);
         assert_eq!(reviews[0].isbn, isbn);
         assert_eq!(reviews[0].username, username);
@@ -175,4 +910,1493 @@ mod tests {
             .await;
         assert!(resp.status().is_success());
     }
+
+    #[actix_web::test]
+    async fn books_api_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let (isbn, title, author, description, language, issue_year) = (
+            Isbn::new(9_785_170_906_307).unwrap(),
+            "Война и мир",
+            "Лев Толстой",
+            "a novel",
+            "ru",
+            1869,
+        );
+
+        let resp = TestRequest::post()
+            .uri("/books")
+            .set_payload(
+                NewBook {
+                    isbn,
+                    title: title.into(),
+                    author: author.into(),
+                    description: description.into(),
+                    language: language.into(),
+                    issue_year,
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+
+        let req = TestRequest::get()
+            .uri(&format!("/books/{isbn}"))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let book = Book::read_from_buffer(&resp).unwrap();
+        assert_eq!(book.isbn, isbn);
+        assert_eq!(book.title, title);
+        assert_eq!(book.author, author);
+        assert_eq!(book.description, description);
+        assert_eq!(book.language, language);
+        assert_eq!(book.issue_year, issue_year);
+
+        let title = "War and Peace";
+        let language = "en";
+        let resp = TestRequest::put()
+            .uri("/books")
+            .set_payload(
+                NewBook {
+                    isbn,
+                    title: title.into(),
+                    author: author.into(),
+                    description: description.into(),
+                    language: language.into(),
+                    issue_year,
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+
+        let req = TestRequest::get()
+            .uri(&format!("/books/{isbn}"))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let book = Book::read_from_buffer(&resp).unwrap();
+        assert_eq!(book.title, title);
+        assert_eq!(book.language, language);
+
+        let req = TestRequest::patch()
+            .uri(&format!("/books/{isbn}"))
+            .set_payload(
+                BookChanges {
+                    issue_year: Some(1867),
+                    ..Default::default()
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let book = Book::read_from_buffer(&resp).unwrap();
+        assert_eq!(book.title, title);
+        assert_eq!(book.issue_year, 1867);
+
+        let resp = TestRequest::delete()
+            .uri(&format!("/books/{isbn}"))
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn contributors_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let isbn = Isbn::new(9_780_000_000_606).unwrap();
+        let resp = TestRequest::post()
+            .uri("/books")
+            .set_payload(
+                NewBook {
+                    isbn,
+                    title: "Good Omens".into(),
+                    author: "Terry Pratchett, Neil Gaiman".into(),
+                    description: "".into(),
+                    language: "en".into(),
+                    issue_year: 1990,
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+
+        let req = TestRequest::get()
+            .uri(&format!("/books/{isbn}/contributors"))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let contributors = Vec::<Contributor>::read_from_buffer(&resp).unwrap();
+        let names: Vec<&str> = contributors.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["Terry Pratchett", "Neil Gaiman"]);
+        assert!(contributors
+            .iter()
+            .all(|c| c.role == ContributorRole::Author));
+
+        let req = TestRequest::put()
+            .uri(&format!("/books/{isbn}/contributors"))
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .insert_header((header::ACCEPT, "application/json"))
+            .set_payload(
+                r#"[
+                    {"name": "Neil Gaiman", "role": "author"},
+                    {"name": "Terry Pratchett", "role": "author"},
+                    {"name": "Contributors Test Translator", "role": "translator"}
+                ]"#,
+            )
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let contributors: Vec<Contributor> = serde_json::from_slice(&resp).unwrap();
+        assert_eq!(contributors.len(), 3);
+        let translator = contributors[2].author_id;
+
+        let req = TestRequest::get()
+            .uri(&format!("/books/{isbn}"))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let book = Book::read_from_buffer(&resp).unwrap();
+        assert_eq!(book.author, "Neil Gaiman; Terry Pratchett");
+
+        let req = TestRequest::get()
+            .uri("/authors?q=contributors%20test")
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let authors = Vec::<Author>::read_from_buffer(&resp).unwrap();
+        assert_eq!(authors.len(), 1);
+        assert_eq!(authors[0].id, translator);
+        for (role, count) in [("translator", 1), ("author", 0)] {
+            let req = TestRequest::get()
+                .uri(&format!("/authors/{translator}/books?role={role}"))
+                .to_request();
+            let resp = call_and_read_body(&app, req).await;
+            let books = Vec::<Book>::read_from_buffer(&resp).unwrap();
+            assert_eq!(books.len(), count);
+        }
+
+        // a new byline replaces the authors but keeps the translator
+        let req = TestRequest::patch()
+            .uri(&format!("/books/{isbn}"))
+            .set_payload(
+                BookChanges {
+                    author: Some("Terry Pratchett".into()),
+                    ..Default::default()
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .to_request();
+        call_and_read_body(&app, req).await;
+        let req = TestRequest::get()
+            .uri(&format!("/books/{isbn}/contributors"))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let contributors = Vec::<Contributor>::read_from_buffer(&resp).unwrap();
+        let names: Vec<&str> = contributors.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["Terry Pratchett", "Contributors Test Translator"]);
+
+        let resp = TestRequest::put()
+            .uri(&format!("/books/{isbn}/contributors"))
+            .set_payload(
+                vec![NewContributor {
+                    name: "Contributors Test Translator".into(),
+                    role: ContributorRole::Translator,
+                }]
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = ErrorBody::read_from_buffer(&test::read_body(resp).await).unwrap();
+        assert_eq!(body.fields[0].field, Field::Contributors);
+
+        let resp = TestRequest::delete()
+            .uri(&format!("/books/{isbn}"))
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn author_filter_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let books = [
+            (
+                Isbn::new(9_780_000_000_675).unwrap(),
+                "Лев Толстой",
+                "Война и мир",
+                "ru",
+            ),
+            (
+                Isbn::new(9_780_000_000_682).unwrap(),
+                "Leo Tolstoy",
+                "War and Peace",
+                "en",
+            ),
+        ];
+        let isbns = books.map(|(isbn, ..)| isbn);
+        for (isbn, author, title, language) in books {
+            let resp = TestRequest::post()
+                .uri("/books")
+                .set_payload(
+                    NewBook {
+                        isbn,
+                        title: title.into(),
+                        author: author.into(),
+                        description: "author filter test".into(),
+                        language: language.into(),
+                        issue_year: 1869,
+                    }
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+
+        // listing and search select the same books, whatever the spelling
+        for (author, expected) in [
+            ("Tolstoy", &isbns[..]),
+            ("Tolstoj", &isbns),
+            ("Толстой", &isbns),
+            ("tolstoi", &isbns),
+            ("Tolstaya", &[]),
+        ] {
+            let req = TestRequest::get()
+                .uri(&format!(
+                    "/books?sort=isbn&limit=100&author={}",
+                    urlencode(author)
+                ))
+                .to_request();
+            let resp = call_and_read_body(&app, req).await;
+            let page = Page::<Book>::read_from_buffer(&resp).unwrap();
+            let listed: Vec<Isbn> = page
+                .items
+                .iter()
+                .map(|book| book.isbn)
+                .filter(|isbn| isbns.contains(isbn))
+                .collect();
+            assert_eq!(listed, expected, "listing by {author}");
+
+            let req = TestRequest::get()
+                .uri(&format!(
+                    "/books/search?q=author%20filter%20test&author={}",
+                    urlencode(author)
+                ))
+                .to_request();
+            let resp = call_and_read_body(&app, req).await;
+            let hits = Vec::<SearchHit>::read_from_buffer(&resp).unwrap();
+            let mut found: Vec<Isbn> = hits
+                .iter()
+                .map(|hit| hit.book.isbn)
+                .filter(|isbn| isbns.contains(isbn))
+                .collect();
+            found.sort();
+            assert_eq!(found, expected, "search by {author}");
+        }
+
+        for isbn in isbns {
+            let resp = TestRequest::delete()
+                .uri(&format!("/books/{isbn}"))
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+    }
+
+    #[actix_web::test]
+    async fn authors_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let books = [
+            (Isbn::new(9_780_000_000_613).unwrap(), "Аркадий Стругацкий"),
+            (Isbn::new(9_780_000_000_620).unwrap(), "Arkady Strugatsky"),
+        ];
+        for (isbn, author) in books {
+            let resp = TestRequest::post()
+                .uri("/books")
+                .set_payload(
+                    NewBook {
+                        isbn,
+                        title: "Пикник на обочине".into(),
+                        author: author.into(),
+                        description: "".into(),
+                        language: "ru".into(),
+                        issue_year: 1972,
+                    }
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+        let find = |query: &str| {
+            TestRequest::get()
+                .uri(&format!("/authors?q={}", urlencode(query)))
+                .to_request()
+        };
+
+        // both spellings are found in either script
+        for query in ["Strugatskij", "стругацкий"] {
+            let resp = call_and_read_body(&app, find(query)).await;
+            let authors = Vec::<Author>::read_from_buffer(&resp).unwrap();
+            let names: Vec<&str> = authors.iter().map(|a| a.name.as_str()).collect();
+            assert_eq!(names, ["Arkady Strugatsky", "Аркадий Стругацкий"]);
+        }
+        let resp = call_and_read_body(&app, find("Strugatsky")).await;
+        let authors = Vec::<Author>::read_from_buffer(&resp).unwrap();
+        let (latin, cyrillic) = (authors[0].id, authors[1].id);
+
+        let req = TestRequest::post()
+            .uri(&format!("/authors/{cyrillic}/merge/{latin}"))
+            .to_request();
+        call_and_read_body(&app, req).await;
+        let resp = call_and_read_body(&app, find("Strugatsky")).await;
+        let authors = Vec::<Author>::read_from_buffer(&resp).unwrap();
+        assert_eq!(authors.len(), 1);
+        assert_eq!(authors[0].id, cyrillic);
+
+        let req = TestRequest::get()
+            .uri(&format!("/authors/{cyrillic}/names"))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let names = Vec::<AuthorName>::read_from_buffer(&resp).unwrap();
+        assert_eq!(
+            names,
+            [AuthorName {
+                name: "Arkady Strugatsky".into(),
+                script: Script::Latin,
+            }]
+        );
+        let req = TestRequest::get()
+            .uri(&format!("/books/{}", books[1].0))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let book = Book::read_from_buffer(&resp).unwrap();
+        assert_eq!(book.author, "Аркадий Стругацкий");
+
+        let req = TestRequest::put()
+            .uri(&format!("/authors/{cyrillic}/name"))
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(r#""Arkady Strugatsky""#)
+            .to_request();
+        call_and_read_body(&app, req).await;
+        let req = TestRequest::get()
+            .uri(&format!("/books/{}", books[0].0))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let book = Book::read_from_buffer(&resp).unwrap();
+        assert_eq!(book.author, "Arkady Strugatsky");
+
+        // a contributor given under an alternate name is the same author
+        let resp = TestRequest::post()
+            .uri(&format!("/authors/{cyrillic}/names"))
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(r#"{"name": "A. Strugatsky"}"#)
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+        let resp = TestRequest::patch()
+            .uri(&format!("/books/{}", books[1].0))
+            .set_payload(
+                BookChanges {
+                    author: Some("A. Strugatsky".into()),
+                    ..Default::default()
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+        let body = test::read_body(resp).await;
+        assert_eq!(
+            Book::read_from_buffer(&body).unwrap().author,
+            "Arkady Strugatsky"
+        );
+        let req = TestRequest::get()
+            .uri(&format!("/books/{}/contributors", books[1].0))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let contributors = Vec::<Contributor>::read_from_buffer(&resp).unwrap();
+        assert_eq!(contributors[0].author_id, cyrillic);
+
+        let resp = TestRequest::delete()
+            .uri(&format!("/authors/{cyrillic}"))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        for (isbn, _) in books {
+            let resp = TestRequest::delete()
+                .uri(&format!("/books/{isbn}"))
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+        let resp = TestRequest::delete()
+            .uri(&format!("/authors/{cyrillic}"))
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn works_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+
+        let resp = TestRequest::post()
+            .uri("/works")
+            .set_payload(
+                NewWork {
+                    title: "Solaris".into(),
+                    original_language: "xx".into(),
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = ErrorBody::read_from_buffer(&test::read_body(resp).await).unwrap();
+        assert_eq!(body.fields[0].field, Field::OriginalLanguage);
+        let req = TestRequest::post()
+            .uri("/works")
+            .set_payload(
+                NewWork {
+                    title: "Solaris".into(),
+                    original_language: "pl".into(),
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let work = Work::read_from_buffer(&resp).unwrap();
+        assert_eq!(work.original_language, "pl");
+
+        let books = [
+            (Isbn::new(9_780_000_000_637).unwrap(), "Солярис", "ru"),
+            (Isbn::new(9_780_000_000_644).unwrap(), "Solaris", "de"),
+        ];
+        for (isbn, title, language) in books {
+            let resp = TestRequest::post()
+                .uri("/books")
+                .set_payload(
+                    NewBook {
+                        isbn,
+                        title: title.into(),
+                        author: "Works Test Author".into(),
+                        description: "".into(),
+                        language: language.into(),
+                        issue_year: 1961,
+                    }
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+            let req = TestRequest::put()
+                .uri(&format!("/books/{isbn}/work"))
+                .set_payload(Some(work.id).write_to_vec().unwrap())
+                .to_request();
+            let resp = call_and_read_body(&app, req).await;
+            assert_eq!(
+                Book::read_from_buffer(&resp).unwrap().work_id,
+                Some(work.id)
+            );
+            let resp = TestRequest::post()
+                .uri("/reviews")
+                .set_payload(
+                    NewReviewPart {
+                        isbn,
+                        username: "works_test".into(),
+                        rating: Rating::Four,
+                        description: "a classic".into(),
+                    }
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+
+        let req = TestRequest::get()
+            .uri(&format!("/books/{}/editions", books[0].0))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let editions = Vec::<Book>::read_from_buffer(&resp).unwrap();
+        assert_eq!(editions.len(), 1);
+        assert_eq!(editions[0].language, "de");
+        let req = TestRequest::get()
+            .uri(&format!("/works/{}/editions", work.id))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        assert_eq!(Vec::<Book>::read_from_buffer(&resp).unwrap().len(), 2);
+
+        // one review per page, so the tie on the username spans pages
+        let mut cursor = None::<String>;
+        let mut isbns = Vec::new();
+        loop {
+            let mut uri = format!("/reviews/book/{}?editions=true&limit=1", books[0].0);
+            if let Some(cursor) = &cursor {
+                uri += &format!("&cursor={cursor}");
+            }
+            let req = TestRequest::get().uri(&uri).to_request();
+            let resp = call_and_read_body(&app, req).await;
+            let page = Page::<Review>::read_from_buffer(&resp).unwrap();
+            isbns.extend(page.items.iter().map(|review| review.isbn));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        isbns.sort();
+        assert_eq!(isbns, [books[0].0, books[1].0]);
+        let req = TestRequest::get()
+            .uri(&format!("/reviews/book/{}", books[0].0))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        assert_eq!(
+            Page::<Review>::read_from_buffer(&resp).unwrap().items.len(),
+            1
+        );
+
+        let resp = TestRequest::delete()
+            .uri(&format!("/works/{}", work.id))
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+        for (isbn, ..) in books {
+            let req = TestRequest::get()
+                .uri(&format!("/books/{isbn}"))
+                .to_request();
+            let resp = call_and_read_body(&app, req).await;
+            assert_eq!(Book::read_from_buffer(&resp).unwrap().work_id, None);
+            let resp = TestRequest::delete()
+                .uri(&format!("/books/{isbn}?policy=cascade"))
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+    }
+
+    #[actix_web::test]
+    async fn series_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let title = "Series Test Cycle";
+        // added out of reading order
+        let books = [
+            (Isbn::new(9_780_000_000_668).unwrap(), 2),
+            (Isbn::new(9_780_000_000_651).unwrap(), 1),
+        ];
+        for (isbn, volume) in books {
+            let resp = TestRequest::post()
+                .uri("/books")
+                .set_payload(
+                    NewBook {
+                        isbn,
+                        title: format!("Series Test Volume {volume}").into(),
+                        author: "Series Test Author".into(),
+                        description: "".into(),
+                        language: "en".into(),
+                        issue_year: 2000 + volume,
+                    }
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+            let req = TestRequest::put()
+                .uri(&format!("/books/{isbn}/series"))
+                .set_payload(
+                    vec![NewSeriesEntry {
+                        title: title.into(),
+                        volume,
+                    }]
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .to_request();
+            let resp = call_and_read_body(&app, req).await;
+            let entries = Vec::<SeriesEntry>::read_from_buffer(&resp).unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(
+                (entries[0].title.as_str(), entries[0].volume),
+                (title, volume)
+            );
+        }
+        let req = TestRequest::get()
+            .uri(&format!("/books/{}/series", books[0].0))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let id = Vec::<SeriesEntry>::read_from_buffer(&resp).unwrap()[0].series_id;
+
+        let req = TestRequest::get()
+            .uri(&format!("/series/{id}/books"))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let volumes = Vec::<Volume>::read_from_buffer(&resp).unwrap();
+        let order: Vec<_> = volumes.iter().map(|v| (v.volume, v.book.isbn)).collect();
+        assert_eq!(order, [(1, books[1].0), (2, books[0].0)]);
+
+        let resp = TestRequest::put()
+            .uri(&format!("/books/{}/series", books[0].0))
+            .set_payload(
+                vec![NewSeriesEntry {
+                    title: title.into(),
+                    volume: 0,
+                }]
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = ErrorBody::read_from_buffer(&test::read_body(resp).await).unwrap();
+        assert_eq!(body.fields[0].field, Field::Volume);
+        let resp = TestRequest::post()
+            .uri("/series")
+            .set_payload(
+                NewSeries {
+                    title: title.into(),
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let resp = TestRequest::delete()
+            .uri(&format!("/series/{id}"))
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+        for (isbn, _) in books {
+            let req = TestRequest::get()
+                .uri(&format!("/books/{isbn}/series"))
+                .to_request();
+            let resp = call_and_read_body(&app, req).await;
+            assert!(Vec::<SeriesEntry>::read_from_buffer(&resp)
+                .unwrap()
+                .is_empty());
+            let resp = TestRequest::delete()
+                .uri(&format!("/books/{isbn}"))
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+    }
+
+    #[actix_web::test]
+    async fn errors_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let isbn = Isbn::new(9_780_000_000_002).unwrap();
+
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}"))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body = ErrorBody::read_from_buffer(&test::read_body(resp).await).unwrap();
+        assert_eq!(body.kind, ErrorKind::NotFound);
+
+        // a wrong check digit may be a book stored before ISBNs were validated
+        let resp = TestRequest::get()
+            .uri("/books/9780000000003")
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = TestRequest::get()
+            .uri("/books/978-0-abc")
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = ErrorBody::read_from_buffer(&test::read_body(resp).await).unwrap();
+        assert_eq!(body.kind, ErrorKind::BadRequest);
+
+        let resp = TestRequest::post()
+            .uri("/reviews")
+            .set_payload(
+                NewReviewPart {
+                    isbn,
+                    username: "a username too long".into(),
+                    rating: Rating::Three,
+                    description: " ".into(),
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = ErrorBody::read_from_buffer(&test::read_body(resp).await).unwrap();
+        assert_eq!(body.kind, ErrorKind::Validation);
+        let fields: Vec<Field> = body.fields.iter().map(|error| error.field).collect();
+        assert_eq!(fields, [Field::Isbn, Field::Username, Field::Description]);
+
+        let resp = TestRequest::post()
+            .uri("/books")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .insert_header((header::ACCEPT, "application/json"))
+            .set_payload(format!(
+                r#"{{
+                    "isbn": {isbn},
+                    "title": "",
+                    "author": "anon",
+                    "description": "",
+                    "language": "xx",
+                    "issue_year": 1000
+                }}"#
+            ))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: ErrorBody = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body.kind, ErrorKind::Validation);
+        assert_eq!(body.fields[0].field, Field::Title);
+        assert_eq!(body.fields[0].message, "must not be empty");
+        assert_eq!(body.fields[1].field, Field::IssueYear);
+        assert_eq!(body.fields[2].field, Field::Language);
+
+        let resp = TestRequest::post()
+            .uri("/books")
+            .set_payload(vec![1, 2, 3])
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = TestRequest::delete()
+            .uri(&format!("/reviews/{isbn}/anon"))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// Books stored with any number before ISBNs were validated.
+    #[actix_web::test]
+    async fn legacy_isbn_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let isbn = 1_000_024;
+        diesel::sql_query(format!(
+            "INSERT INTO books (isbn, title, author, description, language, issue_year)
+            VALUES ({isbn}, 'Legacy ISBN Test', 'anon', '', 'en', 2001)"
+        ))
+        .execute(&mut db::establish_connection())
+        .unwrap();
+
+        let req = TestRequest::get()
+            .uri(&format!("/books/{isbn}"))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let book = Book::read_from_buffer(&resp).unwrap();
+        assert_eq!(i64::from(book.isbn), isbn);
+        assert!(!book.isbn.is_valid());
+        let resp = TestRequest::patch()
+            .uri(&format!("/books/{isbn}"))
+            .set_payload(
+                BookChanges {
+                    issue_year: Some(2002),
+                    ..Default::default()
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+        let req = TestRequest::get()
+            .uri("/books?sort=isbn&limit=1")
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        Page::<Book>::read_from_buffer(&resp).unwrap();
+
+        // but new books need a valid one
+        let resp = TestRequest::post()
+            .uri("/books")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(format!(
+                r#"{{
+                    "isbn": {},
+                    "title": "Legacy ISBN Test",
+                    "author": "anon",
+                    "description": "",
+                    "language": "en",
+                    "issue_year": 2001
+                }}"#,
+                isbn + 1
+            ))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = ErrorBody::read_from_buffer(&test::read_body(resp).await).unwrap();
+        assert_eq!(body.fields[0].field, Field::Isbn);
+
+        let resp = TestRequest::delete()
+            .uri(&format!("/books/{isbn}"))
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn json_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let isbn: i64 = 9_780_140_449_136;
+
+        let resp = TestRequest::post()
+            .uri("/books")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload(format!(
+                r#"{{
+                    "isbn": {isbn},
+                    "title": "Crime and Punishment",
+                    "author": "Fyodor Dostoevsky",
+                    "description": "\"Pain and suffering are always inevitable\"",
+                    "language": "en",
+                    "issue_year": 2003
+                }}"#
+            ))
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+
+        let req = TestRequest::get()
+            .uri("/books/978-0-14-044913-6")
+            .insert_header((header::ACCEPT, "application/json"))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let book: Book = serde_json::from_slice(&resp).unwrap();
+        assert_eq!(book.title, "Crime and Punishment");
+        assert_eq!(
+            book.description,
+            "\"Pain and suffering are always inevitable\""
+        );
+        assert_eq!(book.language, "en");
+
+        let resp = TestRequest::post()
+            .uri("/books")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .insert_header((header::ACCEPT, "application/json"))
+            .set_payload(serde_json::to_vec(&book).unwrap())
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let body: ErrorBody = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body.kind, ErrorKind::UniqueViolation);
+
+        let req = TestRequest::get()
+            .uri("/languages")
+            .insert_header((header::ACCEPT, "application/json"))
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let languages: Vec<Language> = serde_json::from_slice(&resp).unwrap();
+        let german = languages
+            .iter()
+            .find(|language| language.code == "de")
+            .unwrap();
+        assert_eq!(german.bibliographic_code, "ger");
+        assert_eq!(german.native_name, "Deutsch");
+
+        let resp = TestRequest::delete()
+            .uri(&format!("/books/{isbn}"))
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn pagination_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let isbns = [9_780_000_000_101, 9_780_000_000_118, 9_780_000_000_125]
+            .map(|isbn| Isbn::new(isbn).unwrap());
+        for (isbn, issue_year) in isbns.into_iter().zip([2001, 2000, 2000]) {
+            let resp = TestRequest::post()
+                .uri("/books")
+                .set_payload(
+                    NewBook {
+                        isbn,
+                        title: "paginated".into(),
+                        author: "anon".into(),
+                        description: "a book".into(),
+                        language: "en".into(),
+                        issue_year,
+                    }
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+
+        let mut seen = Vec::new();
+        let mut uri = "/books?sort=-issue_year&limit=1".to_owned();
+        loop {
+            let req = TestRequest::get().uri(&uri).to_request();
+            let resp = call_and_read_body(&app, req).await;
+            let page = Page::<Book>::read_from_buffer(&resp).unwrap();
+            assert!(page.items.len() <= 1);
+            seen.extend(
+                page.items
+                    .iter()
+                    .filter(|book| isbns.contains(&book.isbn))
+                    .map(|book| book.isbn),
+            );
+            match page.next_cursor {
+                Some(cursor) => uri = format!("/books?sort=-issue_year&limit=1&cursor={cursor}"),
+                None => break,
+            }
+        }
+        assert_eq!(seen, [isbns[0], isbns[2], isbns[1]]);
+
+        let req = TestRequest::get()
+            .uri("/books?sort=isbn&language=en&min_year=2000&max_year=2000&author=ANO")
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let page = Page::<Book>::read_from_buffer(&resp).unwrap();
+        let found: Vec<_> = page
+            .items
+            .iter()
+            .filter(|book| isbns.contains(&book.isbn))
+            .map(|book| book.isbn)
+            .collect();
+        assert_eq!(found, [isbns[1], isbns[2]]);
+
+        let resp = TestRequest::get()
+            .uri("/books?sort=popularity")
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        for isbn in isbns {
+            let resp = TestRequest::delete()
+                .uri(&format!("/books/{isbn}"))
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+    }
+
+    #[actix_web::test]
+    async fn search_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let books: [(Isbn, &str, &str, &str); 2] = [
+            (
+                Isbn::new(9_785_389_062_542).unwrap(),
+                "Преступление и наказание",
+                "Роман о бедном студенте Раскольникове",
+                "ru",
+            ),
+            (
+                Isbn::new(9_783_150_000_014).unwrap(),
+                "Die Verwandlung",
+                "Gregor Samsa erwacht als Ungeziefer",
+                "de",
+            ),
+        ];
+        for (isbn, title, description, language) in books {
+            let resp = TestRequest::post()
+                .uri("/books")
+                .set_payload(
+                    NewBook {
+                        isbn,
+                        title: title.into(),
+                        author: "search test".into(),
+                        description: description.into(),
+                        language: language.into(),
+                        issue_year: 1900,
+                    }
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+
+        // stemmed: "наказания" matches "наказание", "Verwandlungen" matches "Verwandlung"
+        for (q, isbn) in [("наказания", books[0].0), ("Verwandlungen", books[1].0)] {
+            let req = TestRequest::get()
+                .uri(&format!("/books/search?q={}", urlencode(q)))
+                .to_request();
+            let resp = call_and_read_body(&app, req).await;
+            let hits = Vec::<SearchHit>::read_from_buffer(&resp).unwrap();
+            assert_eq!(hits.len(), 1);
+            assert_eq!(hits[0].book.isbn, isbn);
+        }
+
+        // the filter narrows the hits before they are limited
+        let req = TestRequest::get()
+            .uri("/books/search?q=search%20test&author=search%20test&language=de&limit=1")
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let hits = Vec::<SearchHit>::read_from_buffer(&resp).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].book.isbn, books[1].0);
+
+        for (isbn, ..) in books {
+            let resp = TestRequest::delete()
+                .uri(&format!("/books/{isbn}"))
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+    }
+
+    fn urlencode(s: &str) -> String {
+        s.bytes().map(|b| format!("%{b:02X}")).collect()
+    }
+
+    #[actix_web::test]
+    async fn delete_policy_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        for (isbn, policy) in [
+            (9_780_000_000_200, "cascade"),
+            (9_780_000_000_217, "archive"),
+        ] {
+            let isbn = Isbn::new(isbn).unwrap();
+            let resp = TestRequest::post()
+                .uri("/books")
+                .set_payload(
+                    NewBook {
+                        isbn,
+                        title: "reviewed".into(),
+                        author: "anon".into(),
+                        description: "a book with a review".into(),
+                        language: "en".into(),
+                        issue_year: 2020,
+                    }
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+            let resp = TestRequest::post()
+                .uri("/reviews")
+                .set_payload(
+                    NewReviewPart {
+                        isbn,
+                        username: "anon".into(),
+                        rating: Rating::Four,
+                        description: "fine".into(),
+                    }
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+
+            let resp = TestRequest::delete()
+                .uri(&format!("/books/{isbn}"))
+                .send_request(&app)
+                .await;
+            assert_eq!(resp.status(), StatusCode::CONFLICT);
+            let body = ErrorBody::read_from_buffer(&test::read_body(resp).await).unwrap();
+            assert_eq!(body.kind, ErrorKind::HasReviews);
+
+            let resp = TestRequest::delete()
+                .uri(&format!("/books/{isbn}?policy={policy}"))
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+
+        let resp = TestRequest::get()
+            .uri("/books/9780000000200")
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let req = TestRequest::get()
+            .uri("/reviews/book/9780000000200")
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        assert!(Page::<Review>::read_from_buffer(&resp)
+            .unwrap()
+            .items
+            .is_empty());
+
+        let req = TestRequest::get().uri("/books/9780000000217").to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let book = Book::read_from_buffer(&resp).unwrap();
+        assert!(book.archived_at.is_some());
+
+        let req = TestRequest::get()
+            .uri("/books?sort=isbn&archived=true")
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let page = Page::<Book>::read_from_buffer(&resp).unwrap();
+        assert!(page
+            .items
+            .iter()
+            .any(|book| i64::from(book.isbn) == 9_780_000_000_217));
+        let req = TestRequest::get().uri("/books?sort=isbn").to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let page = Page::<Book>::read_from_buffer(&resp).unwrap();
+        assert!(page
+            .items
+            .iter()
+            .all(|book| i64::from(book.isbn) != 9_780_000_000_217));
+
+        let resp = TestRequest::post()
+            .uri("/books/9780000000217/restore")
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+        let resp = TestRequest::post()
+            .uri("/books/9780000000217/restore")
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = TestRequest::delete()
+            .uri("/books/9780000000217?policy=cascade")
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn export_test() {
+        let app = test::init_service(App::new().configure(config)).await;
+        let isbn = Isbn::new(9_780_000_000_507).unwrap();
+        let resp = TestRequest::post()
+            .uri("/books")
+            .set_payload(
+                NewBook {
+                    isbn,
+                    title: "Exported, \"quoted\"".into(),
+                    author: "exporter".into(),
+                    description: "a book <to> export".into(),
+                    language: "de".into(),
+                    issue_year: 1999,
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+        for rating in [Rating::Two, Rating::Five] {
+            let resp = TestRequest::post()
+                .uri("/reviews")
+                .set_payload(
+                    NewReviewPart {
+                        isbn,
+                        username: format!("{rating:?}").into(),
+                        rating,
+                        description: "exported".into(),
+                    }
+                    .write_to_vec()
+                    .unwrap(),
+                )
+                .send_request(&app)
+                .await;
+            assert!(resp.status().is_success());
+        }
+
+        let resp = TestRequest::get()
+            .uri("/export?format=csv&reviews=true&author=exporter")
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/csv"
+        );
+        assert_eq!(
+            test::read_body(resp).await,
+            "isbn,title,author,description,language,issue_year,review_count,average_rating\n\
+             9780000000507,\"Exported, \"\"quoted\"\"\",exporter,a book <to> export,de,1999,2,3.50\n"
+        );
+
+        let req = TestRequest::get()
+            .uri("/export?format=jsonl&author=exporter")
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let row: serde_json::Value = serde_json::from_slice(&resp).unwrap();
+        assert_eq!(row["isbn"], i64::from(isbn));
+        assert_eq!(row["language"], "de");
+        assert!(row.get("review_count").is_none());
+
+        let req = TestRequest::get()
+            .uri("/export?format=marcxml&author=exporter")
+            .to_request();
+        let resp = String::from_utf8(call_and_read_body(&app, req).await.to_vec()).unwrap();
+        assert!(resp.contains(r#"<controlfield tag="001">9780000000507</controlfield>"#));
+        assert!(resp.contains(r#"<subfield code="a">a book &lt;to&gt; export</subfield>"#));
+
+        let req = TestRequest::get()
+            .uri("/export?format=marc&author=exporter")
+            .to_request();
+        let resp = call_and_read_body(&app, req).await;
+        let len: usize = std::str::from_utf8(&resp[..5]).unwrap().parse().unwrap();
+        assert_eq!(len, resp.len());
+        assert_eq!(resp.last(), Some(&0x1d));
+
+        let resp = TestRequest::get()
+            .uri("/export?format=pdf")
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = TestRequest::delete()
+            .uri(&format!("/books/{isbn}?policy=cascade"))
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+    }
+
+    fn upload(uri: &str, data: &[u8]) -> TestRequest {
+        let (body, headers) = create_form_data_payload_and_headers(
+            "file",
+            Some("upload".into()),
+            None,
+            Bytes::copy_from_slice(data),
+        );
+        let mut req = TestRequest::post().uri(uri).set_payload(body);
+        for (name, value) in &headers {
+            req = req.insert_header((name.clone(), value.clone()));
+        }
+        req
+    }
+
+    #[test]
+    fn storage_check_test() {
+        let dir = env::temp_dir().join("library-check-test");
+        let storage = Storage::new(Box::new(LocalStore::new(dir.clone())));
+        let mut conn = db::establish_connection();
+        let (without_files, with_file) = (
+            Isbn::new(9_780_000_000_699).unwrap(),
+            Isbn::new(9_780_000_000_705).unwrap(),
+        );
+        for isbn in [without_files, with_file] {
+            db::create_book(
+                &mut conn,
+                &NewBook {
+                    isbn,
+                    title: "storage check test".into(),
+                    author: "storage check test".into(),
+                    description: "".into(),
+                    language: "en".into(),
+                    issue_year: 2000,
+                },
+            )
+            .unwrap();
+        }
+        let mut files = storage.transaction();
+        files
+            .stage(FileKind::Book, with_file, &mut &b"storage check test"[..])
+            .unwrap();
+        files.run(&mut conn, |_| Ok(())).unwrap();
+        // other tests keep their files elsewhere, so only these books count
+        let missing = |storage: &Storage, conn: &mut PgConnection| {
+            let mut report = storage.check(conn).unwrap();
+            report
+                .missing_files
+                .retain(|file| [without_files, with_file].contains(&file.isbn));
+            report.missing_files
+        };
+
+        // a book without files is not broken
+        assert!(missing(&storage, &mut conn).is_empty());
+
+        let file = storage.file(&mut conn, FileKind::Book, with_file).unwrap();
+        std::fs::remove_file(dir.join("objects").join(&file.sha256)).unwrap();
+        let missing_files = missing(&storage, &mut conn);
+        assert_eq!(missing_files.len(), 1);
+        assert_eq!(
+            (missing_files[0].isbn, missing_files[0].kind),
+            (with_file, FileKind::Book)
+        );
+
+        let report = storage::ConsistencyReport {
+            missing_files,
+            ..Default::default()
+        };
+        storage.repair(&mut conn, &report).unwrap();
+        assert!(missing(&storage, &mut conn).is_empty());
+        assert!(matches!(
+            storage.file(&mut conn, FileKind::Book, with_file),
+            Err(db::Error::NotFound)
+        ));
+        // repairing forgets the file but keeps the book in circulation
+        let book = db::get_book(&mut conn, with_file).unwrap();
+        assert!(book.archived_at.is_none());
+
+        for isbn in [without_files, with_file] {
+            storage
+                .delete_book(&mut conn, isbn, DeletePolicy::Restrict)
+                .unwrap();
+        }
+    }
+
+    #[actix_web::test]
+    async fn files_test() {
+        let dir = env::temp_dir().join("library-files-test");
+        let blobs = LocalStore::new(dir.clone());
+        let app = test::init_service(
+            App::new()
+                .configure(config)
+                .app_data(web::Data::new(Storage::new(Box::new(blobs)))),
+        )
+        .await;
+        let isbn = Isbn::new(9_780_000_000_309).unwrap();
+        let resp = TestRequest::post()
+            .uri("/books")
+            .set_payload(
+                NewBook {
+                    isbn,
+                    title: "Война и мир".into(),
+                    author: "Tolstoy".into(),
+                    description: "a book with files".into(),
+                    language: "ru".into(),
+                    issue_year: 1869,
+                }
+                .write_to_vec()
+                .unwrap(),
+            )
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+
+        let resp = upload(&format!("/books/{isbn}/cover"), b"%PDF-1.7 not an image")
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = upload("/books/9780000000392/file", b"%PDF-1.7")
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let mut cover = Vec::new();
+        DynamicImage::new_rgb8(600, 300)
+            .write_to(&mut Cursor::new(&mut cover), ImageOutputFormat::Png)
+            .unwrap();
+        let req = upload(&format!("/books/{isbn}/cover"), &cover).to_request();
+        let file = BookFile::read_from_buffer(&call_and_read_body(&app, req).await).unwrap();
+        assert_eq!(file.mime, "image/png");
+        assert_eq!(file.size, cover.len() as i64);
+        let pdf = b"%PDF-1.7 the whole book";
+        let resp = upload(&format!("/books/{isbn}/file"), pdf)
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}/cover"))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/png"
+        );
+        assert!(resp
+            .headers()
+            .get(header::CONTENT_DISPOSITION)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("inline"));
+        assert_eq!(test::read_body(resp).await, cover);
+
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}/cover?size=small"))
+            .send_request(&app)
+            .await;
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/jpeg"
+        );
+        let thumbnail = image::load_from_memory(&test::read_body(resp).await).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (96, 48));
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}/cover?size=huge"))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // a stored cover that became unreadable is not the client's fault
+        let stored = dir.join("objects").join(&file.sha256);
+        std::fs::write(&stored, &cover[..64]).unwrap();
+        let medium = format!("{}-{}", file.sha256, ThumbnailSize::Medium.pixels());
+        std::fs::remove_file(dir.join("thumbnails").join(medium)).unwrap();
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}/cover?size=medium"))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        std::fs::write(&stored, &cover).unwrap();
+
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}/file"))
+            .send_request(&app)
+            .await;
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        assert_eq!(resp.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+        let disposition = resp
+            .headers()
+            .get(header::CONTENT_DISPOSITION)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert!(disposition.starts_with("attachment"));
+        assert!(disposition.contains("filename=\"Tolstoy - _____ _ ___.pdf\""));
+        assert!(disposition.contains("filename*=UTF-8''Tolstoy%20%2D%20%D0%92"));
+
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}/file"))
+            .insert_header((header::RANGE, "bytes=9-11"))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_RANGE).unwrap(),
+            format!("bytes 9-11/{}", pdf.len()).as_str()
+        );
+        assert_eq!(&test::read_body(resp).await[..], b"the");
+
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}/file"))
+            .insert_header((header::RANGE, "bytes=9-11"))
+            .insert_header((header::IF_RANGE, "\"stale\""))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}/file"))
+            .insert_header((header::RANGE, "bytes=1000-"))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}/file"))
+            .insert_header((header::IF_NONE_MATCH, etag))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+        let resp = TestRequest::delete()
+            .uri(&format!("/books/{isbn}?policy=cascade"))
+            .send_request(&app)
+            .await;
+        assert!(resp.status().is_success());
+        let resp = TestRequest::get()
+            .uri(&format!("/books/{isbn}/file"))
+            .send_request(&app)
+            .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
 }
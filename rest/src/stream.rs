@@ -0,0 +1,62 @@
+//! Response bodies produced by blocking code, sent in chunks as they are
+//! produced instead of being collected in memory first.
+
+use actix_web::{rt::task, web::Bytes};
+use futures_util::{stream, Stream};
+use std::io::{self, BufWriter, Read, Write};
+use tokio::sync::mpsc;
+
+/// Size of the chunks sent to the client, in bytes.
+const CHUNK_SIZE: usize = 64 << 10;
+
+/// Chunks produced but not sent yet, before the producer waits for the
+/// client.
+const BACKLOG: usize = 4;
+
+/// Streams what `write` writes, running it on the blocking thread pool.
+///
+/// An error once the response has started aborts it, so that the client
+/// does not mistake a truncated body for a complete one. A client that goes
+/// away makes the writes fail, which stops `write`.
+pub fn from_writer<F>(write: F) -> impl Stream<Item = Result<Bytes, db::Error>>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), db::Error> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(BACKLOG);
+    task::spawn_blocking(move || {
+        let mut out = BufWriter::with_capacity(CHUNK_SIZE, ChannelWriter(sender.clone()));
+        let result = write(&mut out).and_then(|()| Ok(out.flush()?));
+        if let Err(e) = result {
+            // fails only if the client is gone
+            let _ = sender.blocking_send(Err(e));
+        }
+    });
+    stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    })
+}
+
+/// Streams what is read from `reader`, reading on the blocking thread pool.
+pub fn from_reader(
+    mut reader: Box<dyn Read + Send>,
+) -> impl Stream<Item = Result<Bytes, db::Error>> {
+    from_writer(move |out| {
+        io::copy(&mut reader, out)?;
+        Ok(())
+    })
+}
+
+struct ChannelWriter(mpsc::Sender<Result<Bytes, db::Error>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}